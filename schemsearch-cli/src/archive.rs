@@ -0,0 +1,134 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use flate2::read::GzDecoder;
+use schemsearch_files::SpongeSchematic;
+use crate::paths::PathFilter;
+use crate::types::SchematicSupplier;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+pub fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else {
+        None
+    }
+}
+
+/// A zip opened once and shared by the suppliers of its entries
+pub struct ZipArchiveFile {
+    pub path: PathBuf,
+    zip: Mutex<zip::ZipArchive<BufReader<File>>>,
+}
+
+pub struct ArchiveEntrySupplier {
+    pub archive: Arc<ZipArchiveFile>,
+    pub entry: String,
+}
+
+impl ArchiveEntrySupplier {
+    pub fn get_schematic(&self) -> Result<SpongeSchematic, String> {
+        // Only the entry is unpacked under the lock, the parsing runs in parallel
+        let data = {
+            let mut zip = self.archive.zip.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut entry = zip.by_name(&self.entry).map_err(|e| e.to_string())?;
+            let mut data = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut data).map_err(|e| e.to_string())?;
+            data
+        };
+        SpongeSchematic::load_data(&mut data.as_slice())
+    }
+}
+
+impl SchematicSupplier for ArchiveEntrySupplier {
+    fn get_name(&self) -> String {
+        entry_name(&self.archive.path, &self.entry)
+    }
+}
+
+pub struct TarSchematicSupplier {
+    pub archive: PathBuf,
+    pub gzip: bool,
+    pub filter: PathFilter,
+    /// The number of schematics in the archive, counted when it is opened
+    pub entries: usize,
+}
+
+impl TarSchematicSupplier {
+    pub fn open(archive: PathBuf, gzip: bool, filter: PathFilter) -> Result<TarSchematicSupplier, String> {
+        let mut entries = 0;
+        walk_tar(&archive, gzip, &filter, |_, _| entries += 1)?;
+        Ok(TarSchematicSupplier { archive, gzip, filter, entries })
+    }
+
+    pub fn for_each_schematic<F>(&self, mut f: F) -> Result<(), String> where F: FnMut(String, Result<SpongeSchematic, String>) {
+        walk_tar(&self.archive, self.gzip, &self.filter, |entry, mut data| f(entry_name(&self.archive, &entry), SpongeSchematic::load_data(&mut data)))
+    }
+}
+
+impl SchematicSupplier for TarSchematicSupplier {
+    fn get_name(&self) -> String {
+        self.archive.display().to_string()
+    }
+}
+
+fn walk_tar<F>(archive: &Path, gzip: bool, filter: &PathFilter, mut f: F) -> Result<(), String> where F: FnMut(String, &mut dyn Read) {
+    let file = BufReader::new(File::open(archive).map_err(|e| e.to_string())?);
+    let reader: Box<dyn Read> = if gzip { Box::new(GzDecoder::new(file)) } else { Box::new(file) };
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(|e| e.to_string())?.to_path_buf();
+        if !filter.accepts(&path) {
+            continue;
+        }
+        f(path.to_string_lossy().to_string(), &mut entry);
+    }
+    Ok(())
+}
+
+fn entry_name(archive: &Path, entry: &str) -> String {
+    format!("{}!/{}", archive.display(), entry)
+}
+
+/// Opens a zip and lists the entries that pass the filter
+pub fn open_zip(archive: &Path, filter: &PathFilter) -> Result<(Arc<ZipArchiveFile>, Vec<String>), String> {
+    let zip = zip::ZipArchive::new(BufReader::new(File::open(archive).map_err(|e| e.to_string())?)).map_err(|e| e.to_string())?;
+    let entries = zip.file_names()
+        .filter(|name| !name.ends_with('/') && filter.accepts(Path::new(name)))
+        .map(|name| name.to_string())
+        .collect();
+    Ok((Arc::new(ZipArchiveFile { path: archive.to_path_buf(), zip: Mutex::new(zip) }), entries))
+}
@@ -0,0 +1,69 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::path::PathBuf;
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap::error::ErrorKind;
+use crate::paths::{load_input, STDIN_PATH};
+
+pub fn convert_command() -> Command {
+    Command::new("convert")
+        .about("Converts a schematic between the supported formats")
+        .arg(
+            Arg::new("input")
+                .help("The schematic to convert, '-' reads it from stdin")
+                .value_hint(ValueHint::FilePath)
+                .required(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The file to write the converted schematic to, '-' writes it to stdout")
+                .value_hint(ValueHint::FilePath)
+                .required(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("format")
+                .help("The format to convert to")
+                .short('f')
+                .long("format")
+                .action(ArgAction::Set)
+                .default_value("sponge2")
+                .value_parser(["sponge2", "sponge3"]),
+        )
+}
+
+pub fn run_convert(cmd: &mut Command, matches: &ArgMatches) {
+    let schem = match load_input(matches.get_one::<String>("input").expect("Couldn't get input")) {
+        Ok(x) => x,
+        Err(e) => cmd.error(ErrorKind::Io, format!("Error while loading schematic: {}", e)).exit(),
+    };
+    let version = match matches.get_one::<String>("format").expect("Couldn't get format").as_str() {
+        "sponge3" => 3,
+        _ => 2,
+    };
+    let output = matches.get_one::<String>("output").expect("Couldn't get output");
+    let result = if output == STDIN_PATH {
+        schem.save_data(&mut std::io::stdout().lock(), version)
+    } else {
+        schem.save(&PathBuf::from(output), version)
+    };
+    if let Err(e) = result {
+        cmd.error(ErrorKind::Io, format!("Error while saving schematic: {}", e)).exit();
+    }
+}
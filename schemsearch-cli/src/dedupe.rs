@@ -0,0 +1,86 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::path::PathBuf;
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap::error::ErrorKind;
+use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use schemsearch_files::SpongeSchematic;
+use schemsearch_lib::similarity::{find_duplicates, fingerprint, Fingerprint};
+use crate::paths::{path_args, PathFilter};
+
+pub fn dedupe_command() -> Command {
+    path_args(Command::new("dedupe"))
+        .about("Finds groups of schematics that are likely copies of each other")
+        .arg(
+            Arg::new("schematic")
+                .help("The schematics or directories to compare")
+                .value_hint(ValueHint::AnyPath)
+                .required(true)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("threshold")
+                .help("The minimum similarity for two schematics to be grouped")
+                .short('t')
+                .long("threshold")
+                .action(ArgAction::Set)
+                .default_value("0.8")
+                .value_parser(|s: &str| s.parse::<f32>().map_err(|e| e.to_string())),
+        )
+        .arg(
+            Arg::new("ignore-data")
+                .help("Ignores block data when comparing")
+                .short('d')
+                .long("ignore-data")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+pub fn run_dedupe(cmd: &mut Command, matches: &ArgMatches) {
+    let paths = PathFilter::from_matches(matches).collect(matches.get_many::<String>("schematic").unwrap().map(PathBuf::from));
+    if paths.is_empty() {
+        cmd.error(ErrorKind::MissingRequiredArgument, "No schematics specified").exit();
+    }
+    let threshold = *matches.get_one::<f32>("threshold").expect("Couldn't get threshold");
+    let ignore_data = matches.get_flag("ignore-data");
+
+    let bar = ProgressBar::new(paths.len() as u64);
+    bar.set_style(ProgressStyle::with_template("[{elapsed}, ETA: {eta}] {wide_bar} {pos}/{len} {per_sec}").unwrap());
+
+    let fingerprints: Vec<(&PathBuf, Fingerprint)> = paths.par_iter().progress_with(bar).filter_map(|path| {
+        match SpongeSchematic::load(path) {
+            Ok(schem) => Some((path, fingerprint(&schem, ignore_data))),
+            Err(e) => {
+                eprintln!("Error while loading schematic ({}): {}", path.display(), e);
+                None
+            }
+        }
+    }).collect();
+
+    let (paths, fingerprints): (Vec<&PathBuf>, Vec<Fingerprint>) = fingerprints.into_iter().unzip();
+    let groups = find_duplicates(&fingerprints, threshold);
+
+    for (i, group) in groups.iter().enumerate() {
+        println!("Group {} ({} schematics):", i + 1, group.members.len());
+        for (a, b, score) in group.pairs.iter() {
+            println!("  '{}' <-> '{}': {:.1}%", paths[*a].display(), paths[*b].display(), score * 100.0);
+        }
+    }
+    println!("Found {} groups of similar schematics", groups.len());
+}
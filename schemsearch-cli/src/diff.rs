@@ -0,0 +1,110 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::path::PathBuf;
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap::error::ErrorKind;
+use schemsearch_files::SpongeSchematic;
+use schemsearch_lib::diff::{diff, DiffKind};
+use crate::paths::{load_input, STDIN_PATH};
+
+pub fn diff_command() -> Command {
+    Command::new("diff")
+        .about("Compares two schematics and reports the changed blocks")
+        .arg(
+            Arg::new("old")
+                .help("The original schematic, '-' reads it from stdin")
+                .value_hint(ValueHint::FilePath)
+                .required(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("new")
+                .help("The changed schematic, '-' reads it from stdin")
+                .value_hint(ValueHint::FilePath)
+                .required(true)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("offset")
+                .help("The position of the new schematic relative to the old one [x,y,z]")
+                .long("offset")
+                .action(ArgAction::Set)
+                .default_value("0,0,0")
+                .allow_hyphen_values(true)
+                .value_parser(parse_offset),
+        )
+        .arg(
+            Arg::new("list")
+                .help("Lists every changed block")
+                .short('l')
+                .long("list")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output")
+                .help("Writes the differences as a schematic, unchanged blocks are structure voids")
+                .short('o')
+                .long("output")
+                .value_hint(ValueHint::FilePath)
+                .action(ArgAction::Set),
+        )
+}
+
+pub fn run_diff(cmd: &mut Command, matches: &ArgMatches) {
+    if matches.get_one::<String>("old") == matches.get_one::<String>("new") && matches.get_one::<String>("old").is_some_and(|x| x == STDIN_PATH) {
+        cmd.error(ErrorKind::ArgumentConflict, "Only one input can be read from stdin").exit();
+    }
+    let old = load(cmd, matches.get_one::<String>("old").unwrap());
+    let new = load(cmd, matches.get_one::<String>("new").unwrap());
+    let offset = *matches.get_one::<[i32; 3]>("offset").expect("Couldn't get offset");
+
+    let diff = diff(&old, &new, offset);
+
+    if matches.get_flag("list") {
+        for change in diff.blocks.iter().chain(diff.block_entities.iter()) {
+            println!("{:?} at x: {}, y: {}, z: {}: {} -> {}", change.kind, change.pos[0], change.pos[1], change.pos[2],
+                     change.old.as_deref().unwrap_or("-"), change.new.as_deref().unwrap_or("-"));
+        }
+    }
+
+    println!("Blocks: {} added, {} removed, {} changed",
+             diff.count_blocks(DiffKind::Added), diff.count_blocks(DiffKind::Removed), diff.count_blocks(DiffKind::Changed));
+    println!("Block entities: {} added, {} removed, {} changed",
+             diff.count_block_entities(DiffKind::Added), diff.count_block_entities(DiffKind::Removed), diff.count_block_entities(DiffKind::Changed));
+
+    if let Some(path) = matches.get_one::<String>("output") {
+        if let Err(e) = diff.to_schematic(&old).save(&PathBuf::from(path), 2) {
+            cmd.error(ErrorKind::Io, format!("Error while writing diff: {}", e)).exit();
+        }
+    }
+}
+
+fn load(cmd: &mut Command, path: &String) -> SpongeSchematic {
+    match load_input(path) {
+        Ok(x) => x,
+        Err(e) => cmd.error(ErrorKind::Io, format!("Error while loading schematic ({}): {}", path, e)).exit(),
+    }
+}
+
+fn parse_offset(s: &str) -> Result<[i32; 3], String> {
+    let parts = s.split(',').map(|x| x.trim().parse::<i32>().map_err(|e| e.to_string())).collect::<Result<Vec<i32>, String>>()?;
+    match parts.as_slice() {
+        [x, y, z] => Ok([*x, *y, *z]),
+        _ => Err("Offset must be x,y,z".to_string()),
+    }
+}
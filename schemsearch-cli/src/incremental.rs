@@ -0,0 +1,172 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fs::{read, read_to_string, rename, write};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use chrono::NaiveDateTime;
+use futures::executor::block_on;
+use serde::{Deserialize, Serialize};
+use schemsearch_lib::SearchBehavior;
+use schemsearch_sql::SchematicDatabase;
+use schemsearch_sql::filter::SchematicFilter;
+
+/// The last scan of each pattern set, stored as JSON
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScanState {
+    scans: Vec<ScanEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ScanEntry {
+    pattern: String,
+    fingerprint: String,
+    last_scan: NaiveDateTime,
+}
+
+impl ScanState {
+    pub fn load(path: &Path) -> Result<ScanState, String> {
+        match read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(ScanState::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let temp = path.with_extension("tmp");
+        write(&temp, serde_json::to_string_pretty(self).map_err(|e| e.to_string())?).map_err(|e| format!("{}: {}", temp.display(), e))?;
+        rename(&temp, path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn last_scan(&self, fingerprint: &str) -> Option<NaiveDateTime> {
+        self.scans.iter().find(|x| x.fingerprint == fingerprint).map(|x| x.last_scan)
+    }
+
+    pub fn update(&mut self, pattern: &str, fingerprint: &str, time: NaiveDateTime) {
+        match self.scans.iter_mut().find(|x| x.fingerprint == fingerprint) {
+            Some(entry) => {
+                entry.pattern = pattern.to_string();
+                entry.last_scan = time;
+            }
+            None => self.scans.push(ScanEntry {
+                pattern: pattern.to_string(),
+                fingerprint: fingerprint.to_string(),
+                last_scan: time,
+            }),
+        }
+    }
+}
+
+/// A search over the database that only loads schematics changed since the last scan with the same pattern, options and filters
+pub struct IncrementalScan {
+    path: PathBuf,
+    state: ScanState,
+    pattern: String,
+    fingerprint: String,
+    started: NaiveDateTime,
+    since: Option<NaiveDateTime>,
+    filter: SchematicFilter,
+}
+
+impl IncrementalScan {
+    /// Uses the clock of `database`, see `SchematicDatabase::current_time`
+    pub fn start(database: &SchematicDatabase, path: &Path, pattern: &str, search_behavior: &SearchBehavior, filter: SchematicFilter) -> Result<IncrementalScan, String> {
+        let pattern_data = read(pattern).map_err(|e| format!("Incremental scans need a pattern file ({}): {}", pattern, e))?;
+        let behavior = serde_json::to_string(search_behavior).map_err(|e| e.to_string())?;
+        let fingerprint = fingerprint(&[&pattern_data, behavior.as_bytes(), format!("{:?}", filter).as_bytes()]);
+        let state = ScanState::load(path)?;
+        // Taken before loading, so changes made during the scan are picked up next time
+        let started = block_on(database.current_time())?;
+        let since = state.last_scan(&fingerprint);
+        // Inclusive, the clock has only second precision and nodes changed in the second of the last start must be searched again
+        let filter = match since {
+            Some(since) => filter.modified_since(since),
+            None => filter,
+        };
+        Ok(IncrementalScan {
+            path: path.to_path_buf(),
+            state,
+            pattern: pattern.to_string(),
+            fingerprint,
+            started,
+            since,
+            filter,
+        })
+    }
+
+    pub fn started(&self) -> NaiveDateTime {
+        self.started
+    }
+
+    pub fn since(&self) -> Option<NaiveDateTime> {
+        self.since
+    }
+
+    pub fn filter(&self) -> SchematicFilter {
+        self.filter.clone()
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.state.update(&self.pattern, &self.fingerprint, self.started);
+        self.state.save(&self.path)
+    }
+}
+
+/// FNV-1a, stable across builds unlike the std hashers
+fn fingerprint(parts: &[&[u8]]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in part.iter().chain((part.len() as u64).to_le_bytes().iter()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint(&[b"abc", b"def"]), fingerprint(&[b"abc", b"def"]));
+        assert_ne!(fingerprint(&[b"abc", b"def"]), fingerprint(&[b"abcd", b"ef"]));
+        assert_eq!(fingerprint(&[]).len(), 16);
+    }
+
+    #[test]
+    fn test_scan_state() {
+        let path = std::env::temp_dir().join(format!("schemsearch-scan-state-{}.json", std::process::id()));
+        let time = NaiveDateTime::parse_from_str("2024-06-01 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let mut state = ScanState::load(&path).unwrap();
+        assert_eq!(state.last_scan("a"), None);
+
+        state.update("cannon.schem", "a", time);
+        state.update("cannon.schem", "b", time);
+        state.update("cannon.schem", "a", time + chrono::Duration::hours(1));
+        state.save(&path).unwrap();
+        let state = ScanState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(state.scans.len(), 2);
+        assert_eq!(state.last_scan("a"), Some(time + chrono::Duration::hours(1)));
+        assert_eq!(state.last_scan("b"), Some(time));
+    }
+}
@@ -0,0 +1,66 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::path::PathBuf;
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap::error::ErrorKind;
+use schemsearch_index::SchematicIndex;
+use crate::paths::{path_args, PathFilter};
+
+pub fn index_command() -> Command {
+    Command::new("index")
+        .about("Manages the on-disk schematic index")
+        .subcommand_required(true)
+        .subcommand(
+            path_args(Command::new("build"))
+                .about("Builds or updates an index, only changed schematics are decoded again")
+                .arg(
+                    Arg::new("schematic")
+                        .help("The schematics or directories to index")
+                        .value_hint(ValueHint::AnyPath)
+                        .required(true)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("index")
+                        .help("The index file")
+                        .short('i')
+                        .long("index")
+                        .value_hint(ValueHint::FilePath)
+                        .action(ArgAction::Set)
+                        .default_value("schemsearch.idx"),
+                ),
+        )
+}
+
+pub fn run_index(cmd: &mut Command, matches: &ArgMatches) {
+    if let Some(("build", matches)) = matches.subcommand() {
+        let paths = PathFilter::from_matches(matches).collect(matches.get_many::<String>("schematic").unwrap().map(PathBuf::from));
+        let index = PathBuf::from(matches.get_one::<String>("index").unwrap());
+
+        let stats = match SchematicIndex::build(&index, &paths) {
+            Ok(x) => x,
+            Err(e) => cmd.error(ErrorKind::Io, format!("Error while building index: {}", e)).exit(),
+        };
+
+        for (path, e) in stats.errors.iter() {
+            eprintln!("Error while loading schematic ({}): {}", path, e);
+        }
+        println!("Indexed {} schematics ({} added, {} updated, {} unchanged, {} removed, {} failed)",
+                 stats.added + stats.updated + stats.unchanged, stats.added, stats.updated, stats.unchanged, stats.removed, stats.errors.len());
+    }
+}
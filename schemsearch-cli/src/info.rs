@@ -0,0 +1,130 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::BTreeMap;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use schemsearch_files::SpongeSchematic;
+use schemsearch_lib::stats::{stats, SchematicStats};
+use crate::input::{check_stdin, collect_schematics, input_args};
+use crate::json_output::InfoOutput;
+
+pub fn info_command() -> Command {
+    input_args(Command::new("info"))
+        .about("Shows the dimensions, palette, data version, metadata and block statistics of schematics")
+        .arg(
+            Arg::new("format")
+                .help("The output format")
+                .short('f')
+                .long("format")
+                .action(ArgAction::Set)
+                .default_value("table")
+                .value_parser(["table", "json", "csv"]),
+        )
+        .arg(
+            Arg::new("states")
+                .help("Counts blocks by their full block state in the table output")
+                .long("states")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+pub fn run_info(cmd: &mut Command, matches: &ArgMatches) {
+    check_stdin(cmd, matches, &["schematic"]);
+    let format = matches.get_one::<String>("format").expect("Couldn't get format").as_str();
+    let states = matches.get_flag("states");
+
+    if format == "csv" {
+        println!("Name,Category,Key,Count");
+    }
+    for schem in collect_schematics(cmd, matches, None) {
+        schem.for_each_schematic(|name, schematic| match schematic {
+            Ok(schematic) => {
+                let stats = stats(&schematic);
+                match format {
+                    "json" => print_json(name, &schematic, stats),
+                    "csv" => print_csv(&name, &stats),
+                    _ => print_table(&name, &schematic, &stats, states),
+                }
+            }
+            Err(e) => eprintln!("Error while loading schematic ({}): {}", name, e),
+        });
+    }
+}
+
+fn print_table(name: &str, schem: &SpongeSchematic, stats: &SchematicStats, states: bool) {
+    let origin = schem.origin();
+    println!("{}", name);
+    println!("  Data version: {}", schem.data_version);
+    println!("  Size: {}x{}x{} ({} blocks, {} non-air)", stats.width, stats.height, stats.length, schem.block_data.len(), stats.volume);
+    if let Some(bounding_box) = stats.bounding_box {
+        println!("  Bounding box: {:?} to {:?}", bounding_box.min, bounding_box.max);
+    }
+    println!("  Offset: {},{},{}", schem.offset[0], schem.offset[1], schem.offset[2]);
+    println!("  Origin: {},{},{}", origin[0], origin[1], origin[2]);
+    println!("  Palette: {} entries", schem.palette.len());
+    println!("  Entities: {}", schem.entities.as_ref().map(|x| x.len()).unwrap_or(0));
+
+    if schem.metadata.iter().next().is_some() {
+        println!("  Metadata:");
+        for (key, value) in schem.metadata.iter() {
+            println!("    {}: {}", key, value);
+        }
+    }
+
+    println!("  Blocks:");
+    print_counts(if states { &stats.blocks } else { &stats.block_types });
+    if !stats.block_entities.is_empty() {
+        println!("  Block entities:");
+        print_counts(&stats.block_entities);
+    }
+}
+
+fn print_counts(counts: &BTreeMap<String, u64>) {
+    let mut counts: Vec<(&String, &u64)> = counts.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    for (name, count) in counts {
+        println!("    {:>8}  {}", count, name);
+    }
+}
+
+fn print_json(name: String, schem: &SpongeSchematic, stats: SchematicStats) {
+    let output = InfoOutput {
+        name,
+        data_version: schem.data_version,
+        offset: schem.offset,
+        origin: schem.origin(),
+        palette: schem.palette.len(),
+        entities: schem.entities.as_ref().map(|x| x.len()).unwrap_or(0),
+        metadata: schem.metadata.iter().map(|(k, v)| (k.clone(), v.to_string())).collect(),
+        stats,
+    };
+    println!("{}", serde_json::to_string(&output).unwrap());
+}
+
+fn print_csv(name: &str, stats: &SchematicStats) {
+    let name = csv_quote(name);
+    println!("{},volume,non_air,{}", name, stats.volume);
+    for (category, counts) in [("block", &stats.block_types), ("state", &stats.blocks), ("block_entity", &stats.block_entities)] {
+        for (key, count) in counts {
+            println!("{},{},{},{}", name, category, csv_quote(key), count);
+        }
+    }
+}
+
+fn csv_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
@@ -0,0 +1,491 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#[cfg(feature = "sql")]
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+#[cfg(feature = "sql")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap::error::ErrorKind;
+#[cfg(feature = "sql")]
+use chrono::NaiveDateTime;
+#[cfg(feature = "sql")]
+use futures::executor::block_on;
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use schemsearch_files::SpongeSchematic;
+use schemsearch_index::SchematicIndex;
+use schemsearch_lib::SearchBehavior;
+#[cfg(feature = "sql")]
+use schemsearch_sql::config::ConnectionConfig;
+#[cfg(feature = "sql")]
+use schemsearch_sql::filter::{parse_date, SchematicFilter};
+#[cfg(feature = "sql")]
+use schemsearch_sql::SchematicDatabase;
+#[cfg(feature = "sql")]
+use schemsearch_sql::loader::LoaderOptions;
+use crate::archive::{archive_kind, open_zip, ArchiveEntrySupplier, ArchiveKind, TarSchematicSupplier};
+use crate::paths::{path_args, read_stdin, PathFilter, STDIN_PATH};
+use crate::stderr::progress_bar;
+#[cfg(feature = "sql")]
+use crate::types::SqlSchematicSupplier;
+use crate::types::{IndexSchematicSupplier, PathSchematicSupplier, ScheduledSchematic, SchematicSupplierType, StdinSchematicSupplier};
+
+pub fn input_args(cmd: Command) -> Command {
+    let cmd = path_args(cmd)
+        .arg(
+            Arg::new("schematic")
+                .help("The schematics, directories or archives to use, '-' reads one from stdin")
+                .value_hint(ValueHint::AnyPath)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("stdin-paths")
+                .help("Reads a list of schematic paths from stdin, one per line")
+                .long("stdin-paths")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("null")
+                .help("Separates the paths read with --stdin-paths by NUL instead of newlines")
+                .short('0')
+                .long("null")
+                .requires("stdin-paths")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("index")
+                .help("Uses the schematics stored in an index built with 'schemsearch index build'")
+                .short('i')
+                .long("index")
+                .value_hint(ValueHint::FilePath)
+                .action(ArgAction::Append),
+        );
+
+    #[cfg(feature = "sql")]
+    let cmd = cmd
+        .arg(
+            Arg::new("sql")
+                .help("Use the SteamWar SQL Database")
+                .short('s')
+                .long("sql")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sql-filter-user")
+                .help("Filter the schematics by the owners userid")
+                .short('u')
+                .long("sql-filter-user")
+                .action(ArgAction::Append)
+                .value_parser(|s: &str| s.parse::<u32>().map_err(|e| e.to_string()))
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-filter-name")
+                .help("Filter the schematics by the schematic name")
+                .short('n')
+                .long("sql-filter-name")
+                .action(ArgAction::Append)
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-filter-type")
+                .help("Filter the schematics by their node type, e.g. WARSHIP")
+                .long("sql-filter-type")
+                .action(ArgAction::Append)
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-filter-modified-after")
+                .help("Filter the schematics changed after a date [YYYY-MM-DD or YYYY-MM-DD HH:MM:SS]")
+                .long("sql-filter-modified-after")
+                .action(ArgAction::Set)
+                .value_parser(|s: &str| parse_date(s))
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-filter-parent")
+                .help("Filter the schematics inside a folder node, including subfolders")
+                .long("sql-filter-parent")
+                .action(ArgAction::Append)
+                .value_parser(|s: &str| s.parse::<i32>().map_err(|e| e.to_string()))
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-filter-id")
+                .help("Filter the schematics by their node id")
+                .long("sql-filter-id")
+                .action(ArgAction::Append)
+                .value_parser(|s: &str| s.parse::<i32>().map_err(|e| e.to_string()))
+                .requires("sql"),
+        );
+    #[cfg(feature = "sql")]
+    let cmd = sql_args(cmd);
+
+    cmd
+}
+
+/// Connection settings for `--sql`, they override `SCHEMSEARCH_SQL_*` and the config file
+#[cfg(feature = "sql")]
+pub fn sql_args(cmd: Command) -> Command {
+    cmd
+        .arg(
+            Arg::new("sql-url")
+                .help("The database URL, e.g. mysql://user@host/database, postgres://... or sqlite://file.db")
+                .long("sql-url")
+                .action(ArgAction::Set)
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-config")
+                .help("The database config file [default: ~/mysql.properties]")
+                .long("sql-config")
+                .value_hint(ValueHint::FilePath)
+                .action(ArgAction::Set)
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-host")
+                .help("The database host")
+                .long("sql-host")
+                .action(ArgAction::Set)
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-port")
+                .help("The database port")
+                .long("sql-port")
+                .action(ArgAction::Set)
+                .value_parser(|s: &str| s.parse::<u16>().map_err(|e| e.to_string()))
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-user")
+                .help("The database user")
+                .long("sql-user")
+                .action(ArgAction::Set)
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-password")
+                .help("The database password, prefer SCHEMSEARCH_SQL_PASSWORD to keep it out of the process list")
+                .long("sql-password")
+                .action(ArgAction::Set)
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-database")
+                .help("The database name")
+                .long("sql-database")
+                .action(ArgAction::Set)
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-pool-size")
+                .help("The maximum number of database connections [default: 5]")
+                .long("sql-pool-size")
+                .action(ArgAction::Set)
+                .value_parser(|s: &str| match s.parse::<u32>() {
+                    Ok(0) => Err("must be at least 1".to_string()),
+                    x => x.map_err(|e| e.to_string()),
+                })
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-tls")
+                .help("The TLS mode of the database connection")
+                .long("sql-tls")
+                .action(ArgAction::Set)
+                .value_parser(["disable", "prefer", "require", "verify-ca", "verify-full"])
+                .requires("sql"),
+        )
+}
+
+#[cfg(feature = "sql")]
+pub fn connect_sql(cmd: &mut Command, matches: &ArgMatches) -> Arc<SchematicDatabase> {
+    let overrides = ConnectionConfig {
+        url: matches.get_one::<String>("sql-url").cloned(),
+        host: matches.get_one::<String>("sql-host").cloned(),
+        port: matches.get_one::<u16>("sql-port").copied(),
+        user: matches.get_one::<String>("sql-user").cloned(),
+        password: matches.get_one::<String>("sql-password").cloned(),
+        database: matches.get_one::<String>("sql-database").cloned(),
+        pool_size: matches.get_one::<u32>("sql-pool-size").copied(),
+        tls: matches.get_one::<String>("sql-tls").map(|x| x.parse().unwrap()),
+        mapping: None,
+    };
+    let (config, warnings) = ConnectionConfig::load(matches.get_one::<String>("sql-config").map(Path::new), overrides)
+        .unwrap_or_else(|e| cmd.error(ErrorKind::InvalidValue, format!("Invalid database config: {}", e)).exit());
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+    match block_on(SchematicDatabase::connect(&config)) {
+        Ok(x) => Arc::new(x),
+        Err(e) => cmd.error(ErrorKind::Io, e).exit(),
+    }
+}
+
+pub fn worker_args(cmd: Command) -> Command {
+    let cmd = cmd
+        .arg(
+            Arg::new("threads")
+                .help("The number of threads to use [0 = all Available Threads]")
+                .short('T')
+                .long("threads")
+                .action(ArgAction::Set)
+                .default_value("0")
+                .value_parser(|s: &str| s.parse::<usize>().map_err(|e| e.to_string())),
+        )
+        .arg(
+            Arg::new("machine")
+                .help("Output for machines")
+                .short('m')
+                .long("machine")
+                .action(ArgAction::Set)
+                .default_value("0")
+                .value_parser(|s: &str| s.parse::<u16>().map_err(|e| e.to_string())),
+        );
+    #[cfg(feature = "sql")]
+    let cmd = sql_loader_args(cmd);
+    cmd
+}
+
+/// Settings of the `SchemdataLoader` used by `par_schematics` and `serve`, see `loader_options`
+#[cfg(feature = "sql")]
+pub fn sql_loader_args(cmd: Command) -> Command {
+    cmd
+        .arg(
+            Arg::new("sql-batch-size")
+                .help("The number of schematics fetched from the database by one query")
+                .long("sql-batch-size")
+                .action(ArgAction::Set)
+                .default_value("16")
+                .value_parser(positive)
+                .requires("sql"),
+        )
+        .arg(
+            Arg::new("sql-concurrency")
+                .help("The number of database queries running at the same time, each one needs a connection of --sql-pool-size")
+                .long("sql-concurrency")
+                .action(ArgAction::Set)
+                .default_value("4")
+                .value_parser(positive)
+                .requires("sql"),
+        )
+}
+
+#[cfg(feature = "sql")]
+pub fn loader_options(matches: &ArgMatches) -> LoaderOptions {
+    LoaderOptions {
+        batch_size: *matches.get_one::<usize>("sql-batch-size").expect("Could not get sql-batch-size"),
+        concurrency: *matches.get_one::<usize>("sql-concurrency").expect("Could not get sql-concurrency"),
+        ..LoaderOptions::default()
+    }
+}
+
+#[cfg(feature = "sql")]
+fn positive(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        x => x.map_err(|e| e.to_string()),
+    }
+}
+
+pub fn progress(matches: &ArgMatches, len: usize) -> ProgressBar {
+    ThreadPoolBuilder::new().num_threads(*matches.get_one::<usize>("threads").expect("Could not get threads")).build_global().unwrap();
+    progress_bar(len as u64, *matches.get_one::<u16>("machine").expect("Could not get machine"))
+}
+
+/// Iterates over the schematics with their index in `schematics` on the rayon pool, call after `progress`
+#[cfg(not(feature = "sql"))]
+pub fn par_schematics<'a>(_matches: &ArgMatches, schematics: &'a [SchematicSupplierType]) -> impl ParallelIterator<Item = (usize, ScheduledSchematic<'a>)> {
+    schematics.par_iter().enumerate().map(|(index, schem)| (index, ScheduledSchematic::Supplier(schem)))
+}
+
+/// Iterates over the schematics with their index in `schematics` on the rayon pool, call after `progress`.
+/// The data of the database schematics is fetched in batches by a loader thread and fed to the workers through a bounded queue,
+/// all of them are fetched from the database of the first one. They arrive in the order the batches finish, sort by the index for the input order.
+#[cfg(feature = "sql")]
+pub fn par_schematics<'a>(matches: &ArgMatches, schematics: &'a [SchematicSupplierType]) -> impl ParallelIterator<Item = (usize, ScheduledSchematic<'a>)> {
+    let mut database = None;
+    let mut ids = Vec::new();
+    let mut nodes = HashMap::new();
+    for (index, schem) in schematics.iter().enumerate() {
+        if let SchematicSupplierType::SQL(schem) = schem {
+            database.get_or_insert(&schem.database);
+            // A data table with several rows per node lists it more than once
+            if nodes.insert(schem.node.id, (index, schem)).is_none() {
+                ids.push(schem.node.id);
+            }
+        }
+    }
+    let loader = database.map(|database| database.stream_schemdata(ids, loader_options(matches)));
+    let fetched = loader.into_iter().flatten().par_bridge().map(move |(id, data)| {
+        let (index, schem) = nodes[&id];
+        (index, ScheduledSchematic::Fetched(schem, data))
+    });
+    schematics.par_iter()
+        .enumerate()
+        .filter(|(_, schem)| schem.node_id().is_none())
+        .map(|(index, schem)| (index, ScheduledSchematic::Supplier(schem)))
+        .chain(fetched)
+}
+
+pub fn check_stdin(cmd: &mut Command, matches: &ArgMatches, inputs: &[&str]) {
+    let stdin_readers = inputs.iter()
+        .filter_map(|id| matches.try_get_many::<String>(id).ok().flatten())
+        .flatten()
+        .filter(|x| x.as_str() == STDIN_PATH)
+        .count()
+        + matches.get_flag("stdin-paths") as usize;
+    if stdin_readers > 1 {
+        cmd.error(ErrorKind::ArgumentConflict, "Only one input can be read from stdin").exit();
+    }
+}
+
+pub fn collect_schematics(cmd: &mut Command, matches: &ArgMatches, pattern: Option<(&SpongeSchematic, &SearchBehavior)>) -> Vec<SchematicSupplierType> {
+    #[cfg(feature = "sql")]
+    if matches.get_flag("sql") {
+        let database = connect_sql(cmd, matches);
+        let filter = sql_filter(matches);
+        let schematics = collect_schematics_filtered(cmd, matches, pattern, &database, filter);
+        return require_schematics(cmd, schematics);
+    }
+
+    let schematics = collect_inputs(cmd, matches, pattern);
+    require_schematics(cmd, schematics)
+}
+
+/// Collects the inputs like `collect_schematics`, but loads the schematics of `database` with `filter`.
+/// Unlike `collect_schematics` the result may be empty.
+#[cfg(feature = "sql")]
+pub fn collect_schematics_filtered(cmd: &mut Command, matches: &ArgMatches, pattern: Option<(&SpongeSchematic, &SearchBehavior)>, database: &Arc<SchematicDatabase>, filter: SchematicFilter) -> Vec<SchematicSupplierType> {
+    let mut schematics = collect_inputs(cmd, matches, pattern);
+    let nodes = match block_on(database.load_all_schematics(&filter)) {
+        Ok(x) => x,
+        Err(e) => cmd.error(ErrorKind::Io, format!("Error while loading schematics from the database: {}", e)).exit(),
+    };
+    let mut seen = HashSet::new();
+    for schem in nodes.into_iter().filter(|node| seen.insert(node.id)) {
+        schematics.push(SchematicSupplierType::SQL(SqlSchematicSupplier {
+            database: database.clone(),
+            node: schem
+        }))
+    };
+    schematics
+}
+
+pub fn require_schematics(cmd: &mut Command, schematics: Vec<SchematicSupplierType>) -> Vec<SchematicSupplierType> {
+    if schematics.is_empty() {
+        cmd.error(ErrorKind::MissingRequiredArgument, "No schematics specified").exit();
+    }
+    schematics
+}
+
+fn collect_inputs(cmd: &mut Command, matches: &ArgMatches, pattern: Option<(&SpongeSchematic, &SearchBehavior)>) -> Vec<SchematicSupplierType> {
+    let mut schematics: Vec<SchematicSupplierType> = Vec::new();
+    let mut paths: Vec<PathBuf> = Vec::new();
+    if let Some(x) = matches.get_many::<String>("schematic") {
+        for path in x {
+            if path == STDIN_PATH {
+                let mut data = Vec::new();
+                if let Err(e) = std::io::stdin().read_to_end(&mut data) {
+                    cmd.error(ErrorKind::Io, format!("Error while reading stdin: {}", e)).exit();
+                }
+                schematics.push(SchematicSupplierType::STDIN(StdinSchematicSupplier { data }));
+            } else {
+                paths.push(PathBuf::from(path));
+            }
+        }
+    }
+    if matches.get_flag("stdin-paths") {
+        match read_stdin(matches.get_flag("null")) {
+            Ok(x) => paths.extend(x),
+            Err(e) => cmd.error(ErrorKind::Io, format!("Error while reading stdin: {}", e)).exit(),
+        }
+    }
+    if !paths.is_empty() {
+        let filter = PathFilter::from_matches(matches);
+        for path in filter.collect_with_archives(paths.into_iter()) {
+            match archive_kind(&path) {
+                Some(ArchiveKind::Zip) => match open_zip(&path, &filter) {
+                    Ok((archive, entries)) => for entry in entries {
+                        schematics.push(SchematicSupplierType::ARCHIVE(ArchiveEntrySupplier { archive: archive.clone(), entry }));
+                    },
+                    Err(e) => eprintln!("Error while reading archive ({}): {}", path.display(), e),
+                },
+                Some(kind) => match TarSchematicSupplier::open(path.clone(), kind == ArchiveKind::TarGz, filter.clone()) {
+                    Ok(schem) => schematics.push(SchematicSupplierType::TAR(schem)),
+                    Err(e) => eprintln!("Error while reading archive ({}): {}", path.display(), e),
+                },
+                None => schematics.push(SchematicSupplierType::PATH(PathSchematicSupplier { path })),
+            }
+        }
+    }
+
+    if let Some(x) = matches.get_many::<String>("index") {
+        for path in x {
+            let index = match SchematicIndex::open(&PathBuf::from(path)) {
+                Ok(x) => Arc::new(x),
+                Err(e) => cmd.error(ErrorKind::Io, format!("Error while loading index: {}", e)).exit(),
+            };
+            let entries = match pattern {
+                Some((pattern, search_behavior)) => match index.candidates(pattern, search_behavior) {
+                    Ok(x) => x,
+                    Err(e) => cmd.error(ErrorKind::Io, format!("Error while loading index: {}", e)).exit(),
+                },
+                None => (0..index.entries.len()).collect(),
+            };
+            for entry in entries {
+                schematics.push(SchematicSupplierType::INDEX(IndexSchematicSupplier {
+                    index: index.clone(),
+                    entry,
+                }));
+            }
+        }
+    }
+
+    schematics
+}
+
+#[cfg(feature = "sql")]
+pub fn sql_filter(matches: &ArgMatches) -> SchematicFilter {
+    let mut filter = SchematicFilter::default();
+    if let Some(x) = matches.get_many::<u32>("sql-filter-user") {
+        filter = filter.user_id(x.collect());
+    }
+    if let Some(x) = matches.get_many::<String>("sql-filter-name") {
+        filter = filter.name(x.collect());
+    }
+    if let Some(x) = matches.get_many::<String>("sql-filter-type") {
+        filter = filter.node_type(x.collect());
+    }
+    if let Some(x) = matches.get_one::<NaiveDateTime>("sql-filter-modified-after") {
+        filter = filter.modified_after(*x);
+    }
+    if let Some(x) = matches.get_many::<i32>("sql-filter-parent") {
+        filter = filter.parent(x.collect());
+    }
+    if let Some(x) = matches.get_many::<i32>("sql-filter-id") {
+        filter = filter.id(x.collect());
+    }
+    filter
+}
@@ -1,5 +1,7 @@
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 use schemsearch_lib::{Match, SearchBehavior};
+use schemsearch_lib::stats::SchematicStats;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "event")]
@@ -26,4 +28,16 @@ pub struct InitEvent {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EndEvent {
     pub end_time: u128,
-}
\ No newline at end of file
+}
+#[derive(Serialize, Debug)]
+pub struct InfoOutput {
+    pub name: String,
+    pub data_version: i32,
+    pub offset: [i32; 3],
+    pub origin: [i32; 3],
+    pub palette: usize,
+    pub entities: usize,
+    pub metadata: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub stats: SchematicStats,
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use glob::Pattern;
+use schemsearch_files::SpongeSchematic;
+use walkdir::WalkDir;
+use crate::archive::archive_kind;
+
+const SCHEMATIC_EXTENSION: &str = "schem";
+pub const STDIN_PATH: &str = "-";
+
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    pub recursive: bool,
+    pub follow_symlinks: bool,
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+}
+
+pub fn path_args(cmd: Command) -> Command {
+    cmd
+        .arg(
+            Arg::new("recursive")
+                .help("Searches directories recursively")
+                .short('r')
+                .long("recursive")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("follow-symlinks")
+                .help("Follows symbolic links inside directories")
+                .short('L')
+                .long("follow-symlinks")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("include")
+                .help("Only uses files matching the glob, relative to the given directory")
+                .long("include")
+                .action(ArgAction::Append)
+                .value_parser(|s: &str| Pattern::new(s).map_err(|e| e.to_string())),
+        )
+        .arg(
+            Arg::new("exclude")
+                .help("Skips files and directories matching the glob, relative to the given directory")
+                .long("exclude")
+                .action(ArgAction::Append)
+                .value_parser(|s: &str| Pattern::new(s).map_err(|e| e.to_string())),
+        )
+}
+
+impl PathFilter {
+    pub fn from_matches(matches: &ArgMatches) -> PathFilter {
+        PathFilter {
+            recursive: matches.get_flag("recursive"),
+            follow_symlinks: matches.get_flag("follow-symlinks"),
+            include: matches.get_many::<Pattern>("include").map(|x| x.cloned().collect()).unwrap_or_default(),
+            exclude: matches.get_many::<Pattern>("exclude").map(|x| x.cloned().collect()).unwrap_or_default(),
+        }
+    }
+
+    pub fn collect(&self, paths: impl Iterator<Item=PathBuf>) -> Vec<PathBuf> {
+        self.collect_internal(paths, false)
+    }
+
+    pub fn collect_with_archives(&self, paths: impl Iterator<Item=PathBuf>) -> Vec<PathBuf> {
+        self.collect_internal(paths, true)
+    }
+
+    pub fn accepts(&self, path: &Path) -> bool {
+        is_schematic(path) && !self.is_excluded(path) && self.is_included(path)
+    }
+
+    fn collect_internal(&self, paths: impl Iterator<Item=PathBuf>, archives: bool) -> Vec<PathBuf> {
+        let mut schematics = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                self.collect_dir(&path, archives, &mut schematics);
+            } else if is_schematic(&path) || (archives && archive_kind(&path).is_some()) {
+                schematics.push(path);
+            } else if path.exists() {
+                eprintln!("Skipping '{}': not a schematic", path.display());
+            } else {
+                eprintln!("Skipping '{}': no such file or directory", path.display());
+            }
+        }
+        schematics
+    }
+
+    fn collect_dir(&self, root: &Path, archives: bool, schematics: &mut Vec<PathBuf>) {
+        let walker = WalkDir::new(root)
+            .min_depth(1)
+            .max_depth(if self.recursive { usize::MAX } else { 1 })
+            .follow_links(self.follow_symlinks)
+            .sort_by_file_name();
+
+        let entries = walker.into_iter().filter_entry(|entry| {
+            !entry.file_type().is_dir() || !self.is_excluded(relative(root, entry.path()))
+        });
+
+        for entry in entries {
+            let entry = match entry {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("Skipping: {}", e);
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = relative(root, entry.path());
+            if archives && archive_kind(path).is_some() {
+                if !self.is_excluded(path) {
+                    schematics.push(entry.into_path());
+                }
+                continue;
+            }
+            if !self.accepts(path) {
+                continue;
+            }
+            schematics.push(entry.into_path());
+        }
+    }
+
+    fn is_included(&self, path: &Path) -> bool {
+        self.include.is_empty() || self.include.iter().any(|p| p.matches_path(path))
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.exclude.iter().any(|p| p.matches_path(path))
+    }
+}
+
+pub fn load_input(path: &str) -> Result<SpongeSchematic, String> {
+    if path == STDIN_PATH {
+        SpongeSchematic::load_data(&mut std::io::stdin().lock())
+    } else {
+        SpongeSchematic::load(&PathBuf::from(path))
+    }
+}
+
+pub fn read_stdin(null_separated: bool) -> Result<Vec<PathBuf>, String> {
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input).map_err(|e| e.to_string())?;
+    Ok(split_paths(&input, null_separated))
+}
+
+fn split_paths(input: &[u8], null_separated: bool) -> Vec<PathBuf> {
+    let separator = if null_separated { b'\0' } else { b'\n' };
+    input.split(|x| *x == separator)
+        .map(|x| &x[..x.iter().rposition(|x| *x != b'\r').map_or(0, |i| i + 1)])
+        .filter(|x| !x.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+/// Paths on unix don't have to be UTF-8, like the ones printed by `find -print0`
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).as_ref())
+}
+
+#[inline]
+fn relative<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
+
+#[inline]
+fn is_schematic(path: &Path) -> bool {
+    path.extension().is_some_and(|x| x.eq_ignore_ascii_case(SCHEMATIC_EXTENSION))
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    #[test]
+    fn test_split_paths() {
+        assert_eq!(split_paths(b"a.schem\r\n\nb c.schem\n", false), vec![PathBuf::from("a.schem"), PathBuf::from("b c.schem")]);
+        assert_eq!(split_paths(b"a\nb.schem\0c.schem\0", true), vec![PathBuf::from("a\nb.schem"), PathBuf::from("c.schem")]);
+        assert!(split_paths(b"", true).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_split_paths_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let paths = split_paths(b"caf\xe9.schem\0ok.schem", true);
+        assert_eq!(paths[0].as_os_str().as_bytes(), b"caf\xe9.schem");
+        assert_eq!(paths[1], PathBuf::from("ok.schem"));
+    }
+
+    #[test]
+    fn test_path_filter() {
+        let root = std::env::temp_dir().join(format!("schemsearch-paths-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for file in ["a.schem", "B.SCHEM", "notes.txt", "backup/old.schem", "ships/x.schem", "ships/deep/y.schem"] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let names = |filter: PathFilter| -> Vec<String> {
+            filter.collect(std::iter::once(root.clone())).iter()
+                .map(|x| relative(&root, x).to_string_lossy().replace('\\', "/"))
+                .collect()
+        };
+        let glob = |x: &str| Pattern::new(x).unwrap();
+
+        assert_eq!(names(PathFilter::default()), vec!["B.SCHEM", "a.schem"]);
+        assert_eq!(names(PathFilter { recursive: true, ..PathFilter::default() }),
+                   vec!["B.SCHEM", "a.schem", "backup/old.schem", "ships/deep/y.schem", "ships/x.schem"]);
+        assert_eq!(names(PathFilter { recursive: true, exclude: vec![glob("backup"), glob("*/deep")], ..PathFilter::default() }),
+                   vec!["B.SCHEM", "a.schem", "ships/x.schem"]);
+        assert_eq!(names(PathFilter { recursive: true, include: vec![glob("ships/*")], exclude: vec![glob("ships/x.*")], ..PathFilter::default() }),
+                   vec!["ships/deep/y.schem"]);
+
+        let filter = PathFilter { include: vec![glob("*.schem")], ..PathFilter::default() };
+        assert!(filter.accepts(Path::new("a.schem")));
+        assert!(!filter.accepts(Path::new("B.SCHEM")));
+        assert!(!filter.accepts(Path::new("notes.txt")));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::Write;
+use std::str::FromStr;
+#[cfg(feature = "sql")]
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap::error::ErrorKind;
+#[cfg(feature = "sql")]
+use futures::executor::block_on;
+use rayon::prelude::*;
+use schemsearch_lib::{Match, SearchBehavior};
+use schemsearch_lib::search::search;
+#[cfg(feature = "sql")]
+use schemsearch_sql::SchematicDatabase;
+#[cfg(feature = "sql")]
+use schemsearch_sql::results::MatchRecord;
+#[cfg(feature = "sql")]
+use crate::incremental::IncrementalScan;
+use crate::input::{check_stdin, collect_schematics, input_args, par_schematics, progress, worker_args};
+#[cfg(feature = "sql")]
+use crate::input::{collect_schematics_filtered, connect_sql, require_schematics, sql_filter};
+use crate::paths::load_input;
+use crate::sinks::{Output, OutputFormat};
+use crate::types::{ScheduledSchematic, SchematicSupplierType};
+
+#[cfg(not(feature = "sql"))]
+const OUTPUT_HELP: &str = "The output format and path [Format:Path] available formats: text, json, csv; available paths: std, err, (file path)";
+#[cfg(feature = "sql")]
+const OUTPUT_HELP: &str = "The output format and path [Format:Path] available formats: text, json, csv; available paths: std, err, (file path); \
+    'sql:<table>' stores the matches of --sql schematics in a database table";
+
+pub fn search_command() -> Command {
+    let cmd = Command::new("search")
+        .about("Searches for a pattern in schematics")
+        .arg(
+            Arg::new("pattern")
+                .help("The pattern to search for, '-' reads it from stdin")
+                .value_hint(ValueHint::FilePath)
+                .required(true)
+                .action(ArgAction::Set),
+        );
+    let cmd = worker_args(input_args(cmd))
+        .arg(
+            Arg::new("ignore-data")
+                .help("Ignores block data when searching")
+                .short('d')
+                .long("ignore-data")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ignore-block-entities")
+                .help("Ignores block entities when searching [Not Implemented]")
+                .short('b')
+                .long("ignore-block-entities")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ignore-entities")
+                .help("Ignores entities when searching [Not Implemented]")
+                .short('e')
+                .long("ignore-entities")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ignore-air")
+                .help("Ignores air when searching")
+                .short('a')
+                .long("ignore-air")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("air-as-any")
+                .help("Treats air as any block when searching")
+                .short('A')
+                .long("air-as-any")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output")
+                .help(OUTPUT_HELP)
+                .short('o')
+                .long("output")
+                .action(ArgAction::Append)
+                .default_value("text:std")
+                .value_parser(|s: &str| Output::from_str(s)),
+        )
+        .arg(
+            Arg::new("threshold")
+                .help("The threshold for the search")
+                .short('t')
+                .long("threshold")
+                .action(ArgAction::Set)
+                .default_value("0.9")
+                .value_parser(|s: &str| s.parse::<f32>().map_err(|e| e.to_string())),
+        )
+        .arg(
+            Arg::new("limit")
+                .help("The maximum number of matches to return [0 = Unlimited]")
+                .short('l')
+                .long("limit")
+                .action(ArgAction::Set)
+                .default_value("50")
+                .value_parser(|s: &str| s.parse::<usize>().map_err(|e| e.to_string())),
+        );
+
+    #[cfg(feature = "sql")]
+    let cmd = cmd.arg(
+        Arg::new("sql-incremental")
+            .help("Only searches the database schematics changed since the last scan with the same pattern, options and filters, which is remembered in this file")
+            .long("sql-incremental")
+            .value_hint(ValueHint::FilePath)
+            .action(ArgAction::Set)
+            .requires("sql"),
+    );
+
+    cmd
+}
+
+pub fn run_search(cmd: &mut Command, matches: &ArgMatches) {
+    let start = std::time::Instant::now();
+
+    let search_behavior = SearchBehavior {
+        ignore_block_data: matches.get_flag("ignore-data"),
+        ignore_block_entities: matches.get_flag("ignore-block-entities"),
+        ignore_air: matches.get_flag("ignore-air"),
+        air_as_any: matches.get_flag("air-as-any"),
+        ignore_entities: matches.get_flag("ignore-entities"),
+        threshold: *matches.get_one::<f32>("threshold").expect("Couldn't get threshold"),
+        invalid_nbt: false,
+    };
+
+    check_stdin(cmd, matches, &["pattern", "schematic"]);
+
+    let pattern_path = matches.get_one::<String>("pattern").expect("Couldn't get pattern");
+    let pattern = match load_input(pattern_path) {
+        Ok(x) => x,
+        Err(e) => cmd.error(ErrorKind::Io, format!("Error while loading Pattern: {}", e)).exit(),
+    };
+
+    #[cfg(feature = "sql")]
+    let database = matches.get_flag("sql").then(|| connect_sql(cmd, matches));
+    #[cfg(feature = "sql")]
+    let incremental = matches.get_one::<String>("sql-incremental").map(|path| {
+        let database = database.as_ref().expect("--sql-incremental requires --sql");
+        match IncrementalScan::start(database, Path::new(path), pattern_path, &search_behavior, sql_filter(matches)) {
+            Ok(x) => x,
+            Err(e) => cmd.error(ErrorKind::Io, format!("Error while starting the incremental scan: {}", e)).exit(),
+        }
+    });
+    #[cfg(feature = "sql")]
+    let schematics = match (&database, &incremental) {
+        (Some(database), Some(scan)) => {
+            if let Some(since) = scan.since() {
+                eprintln!("Searching the schematics changed since {}", since);
+            }
+            collect_schematics_filtered(cmd, matches, Some((&pattern, &search_behavior)), database, scan.filter())
+        }
+        (Some(database), None) => {
+            let schematics = collect_schematics_filtered(cmd, matches, Some((&pattern, &search_behavior)), database, sql_filter(matches));
+            require_schematics(cmd, schematics)
+        }
+        (None, _) => collect_schematics(cmd, matches, Some((&pattern, &search_behavior))),
+    };
+    #[cfg(not(feature = "sql"))]
+    let schematics = collect_schematics(cmd, matches, Some((&pattern, &search_behavior)));
+
+    let mut output: Vec<(OutputFormat, Box<dyn Write>)> = Vec::new();
+    #[cfg(feature = "sql")]
+    let mut sql_tables: Vec<&String> = Vec::new();
+    for x in matches.get_many::<Output>("output").expect("Error") {
+        match x {
+            Output::Stream(format, sink) => output.push((format.clone(), sink.output())),
+            #[cfg(feature = "sql")]
+            Output::Sql(table) => sql_tables.push(table),
+        }
+    }
+    #[cfg(feature = "sql")]
+    let sql_tables: Vec<(&SchematicDatabase, &String)> = match &database {
+        Some(database) => sql_tables.into_iter().map(|table| (database.as_ref(), table)).collect(),
+        None if sql_tables.is_empty() => Vec::new(),
+        None => cmd.error(ErrorKind::MissingRequiredArgument, "The sql output requires --sql").exit(),
+    };
+    #[cfg(feature = "sql")]
+    for (database, table) in &sql_tables {
+        if let Err(e) = block_on(database.create_results_table(table)) {
+            cmd.error(ErrorKind::Io, format!("Error while creating the results table {}: {}", table, e)).exit();
+        }
+    }
+    // The database clock like the incremental scan, so RunAt matches its state
+    #[cfg(feature = "sql")]
+    let run_at = match (&incremental, sql_tables.first()) {
+        (Some(scan), _) => Some(scan.started()),
+        (None, Some((database, _))) => match block_on(database.current_time()) {
+            Ok(x) => Some(x),
+            Err(e) => cmd.error(ErrorKind::Io, format!("Error while reading the database time: {}", e)).exit(),
+        },
+        (None, None) => None,
+    };
+
+    // Tar archives count with all of their entries
+    let total = schematics.iter().map(SchematicSupplierType::count).sum();
+    for x in &mut output {
+        write!(x.1, "{}", x.0.start(total as u32, &search_behavior, start.elapsed().as_millis())).unwrap();
+    }
+
+    let bar = progress(matches, total);
+
+    let max_matching = *matches.get_one::<usize>("limit").expect("Could not get max-matching");
+
+    let load_failed = AtomicBool::new(false);
+    let mut matches: Vec<SearchResult> = par_schematics(matches, &schematics).flat_map_iter(|(index, schem)| {
+        let mut results = Vec::new();
+        if let ScheduledSchematic::Supplier(SchematicSupplierType::INDEX(schem)) = schem {
+            if !schem.fits(&pattern) {
+                bar.inc(1);
+                return results;
+            }
+        }
+        #[cfg(feature = "sql")]
+        let node_id = schem.node_id();
+        schem.for_each_schematic(|name, schematic| {
+            match schematic {
+                Ok(schematic) => results.push(SearchResult {
+                    index,
+                    name,
+                    #[cfg(feature = "sql")]
+                    node_id,
+                    matches: search(schematic, &pattern, search_behavior),
+                }),
+                Err(e) => {
+                    eprintln!("Error while loading schematic ({}): {}", name, e);
+                    load_failed.store(true, Ordering::Relaxed);
+                }
+            }
+            bar.inc(1);
+        });
+        results
+    }).collect();
+    matches.sort_by_key(|result| result.index);
+
+    let mut matches_count = 0;
+    #[cfg(feature = "sql")]
+    let mut records = Vec::new();
+
+    'outer: for matching in matches {
+        let schem_name = matching.name;
+        #[cfg(feature = "sql")]
+        let node_id = matching.node_id;
+        let matching = matching.matches;
+        for x in matching {
+            for out in &mut output {
+                write!(out.1, "{}", out.0.found_match(&schem_name, x)).unwrap();
+            }
+            #[cfg(feature = "sql")]
+            if let (Some(node_id), Some(run_at)) = (node_id, run_at) {
+                records.push(MatchRecord { node_id, pattern: pattern_path.clone(), found: x, run_at });
+            }
+            matches_count += 1;
+            if max_matching != 0 && matches_count >= max_matching {
+                break 'outer;
+            }
+        }
+    }
+
+    #[cfg(feature = "sql")]
+    let mut store_failed = false;
+    #[cfg(feature = "sql")]
+    for (database, table) in &sql_tables {
+        if let Err(e) = block_on(database.insert_matches(table, &records)) {
+            eprintln!("Error while storing the matches in {}: {}", table, e);
+            store_failed = true;
+        }
+    }
+
+    #[cfg(feature = "sql")]
+    if let Some(scan) = incremental {
+        if load_failed.load(Ordering::Relaxed) {
+            eprintln!("Not updating the incremental scan state because some schematics failed to load");
+        } else if store_failed {
+            eprintln!("Not updating the incremental scan state because the matches could not be stored");
+        } else if max_matching != 0 && matches_count >= max_matching {
+            eprintln!("Not updating the incremental scan state because the match limit was reached, use --limit 0 to report all matches");
+        } else if let Err(e) = scan.finish() {
+            eprintln!("Error while saving the incremental scan state: {}", e);
+        }
+    }
+
+    let end = std::time::Instant::now();
+    for x in &mut output {
+        write!(x.1, "{}", x.0.end(end.duration_since(start).as_millis())).unwrap();
+        x.1.flush().unwrap();
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SearchResult {
+    index: usize,
+    name: String,
+    #[cfg(feature = "sql")]
+    node_id: Option<i32>,
+    matches: Vec<Match>,
+}
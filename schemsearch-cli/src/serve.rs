@@ -0,0 +1,635 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+#[cfg(feature = "sql")]
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::PathBuf;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap::error::ErrorKind;
+#[cfg(feature = "sql")]
+use futures::executor::block_on;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use schemsearch_files::SpongeSchematic;
+use schemsearch_lib::SearchBehavior;
+use schemsearch_lib::search::search;
+#[cfg(feature = "sql")]
+use schemsearch_sql::{SchematicDatabase, SchematicNode};
+#[cfg(feature = "sql")]
+use schemsearch_sql::filter::SchematicFilter;
+#[cfg(feature = "sql")]
+use schemsearch_sql::loader::LoaderOptions;
+use crate::json_output::FoundEvent;
+use crate::paths::{path_args, PathFilter};
+#[cfg(feature = "sql")]
+use crate::input::{connect_sql, loader_options, sql_args, sql_loader_args};
+
+pub fn serve_command() -> Command {
+    let cmd = path_args(Command::new("serve"))
+        .about("Serves a local HTTP/JSON API for searching schematics")
+        .arg(
+            Arg::new("directory")
+                .help("The directory to search in")
+                .value_hint(ValueHint::DirPath)
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new("bind")
+                .help("The address to listen on")
+                .short('b')
+                .long("bind")
+                .action(ArgAction::Set)
+                .default_value("127.0.0.1:8080"),
+        )
+        .arg(
+            Arg::new("threads")
+                .help("The number of threads to use [0 = all Available Threads]")
+                .short('T')
+                .long("threads")
+                .action(ArgAction::Set)
+                .default_value("0")
+                .value_parser(|s: &str| s.parse::<usize>().map_err(|e| e.to_string())),
+        );
+
+    #[cfg(feature = "sql")]
+    let cmd = cmd.arg(
+        Arg::new("sql")
+            .help("Allow searching the SteamWar SQL Database")
+            .short('s')
+            .long("sql")
+            .action(ArgAction::SetTrue),
+    );
+    #[cfg(feature = "sql")]
+    let cmd = sql_loader_args(sql_args(cmd));
+
+    cmd
+}
+
+pub fn run_serve(cmd: &mut Command, matches: &ArgMatches) {
+    let directory = matches.get_one::<String>("directory").map(PathBuf::from);
+    #[cfg(feature = "sql")]
+    let sql = matches.get_flag("sql");
+    #[cfg(not(feature = "sql"))]
+    let sql = false;
+    if directory.is_none() && !sql {
+        cmd.error(ErrorKind::MissingRequiredArgument, "No directory specified").exit();
+    }
+    #[cfg(feature = "sql")]
+    let database = sql.then(|| connect_sql(cmd, matches));
+
+    ThreadPoolBuilder::new().num_threads(*matches.get_one::<usize>("threads").expect("Could not get threads")).build_global().unwrap();
+
+    let bind = matches.get_one::<String>("bind").expect("Couldn't get bind address");
+    let server = match Server::http(bind) {
+        Ok(x) => x,
+        Err(e) => cmd.error(ErrorKind::Io, format!("Error while binding to {}: {}", bind, e)).exit(),
+    };
+    eprintln!("Listening on http://{}", bind);
+
+    let search_server = SearchServer::new(directory, PathFilter::from_matches(matches));
+    #[cfg(feature = "sql")]
+    let search_server = search_server.with_database(database, loader_options(matches));
+    let search_server = Arc::new(search_server);
+    search_server.listen(server);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Source {
+    Directory,
+    Sql,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SearchRequest {
+    /// The id of an uploaded pattern
+    pattern: Option<String>,
+    /// The path of a pattern on the server
+    pattern_path: Option<String>,
+    #[serde(default = "default_source")]
+    source: Source,
+    #[serde(default = "default_threshold")]
+    threshold: f32,
+    #[serde(default)]
+    ignore_data: bool,
+    #[serde(default)]
+    ignore_air: bool,
+    #[serde(default)]
+    air_as_any: bool,
+    /// The maximum number of matches [0 = Unlimited]
+    #[serde(default)]
+    limit: usize,
+}
+
+fn default_source() -> Source {
+    Source::Directory
+}
+
+fn default_threshold() -> f32 {
+    0.9
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum JobStatus {
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize)]
+struct JobState {
+    status: JobStatus,
+    total: usize,
+    searched: usize,
+    found: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip)]
+    matches: Vec<FoundEvent>,
+}
+
+struct Job {
+    state: Mutex<JobState>,
+    changed: Condvar,
+}
+
+impl Job {
+    /// The state stays readable after a panic in the search
+    fn state(&self) -> MutexGuard<'_, JobState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn update<F>(&self, f: F) where F: FnOnce(&mut JobState) {
+        f(&mut self.state());
+        self.changed.notify_all();
+    }
+
+    /// Runs `f` and marks the job as failed if it panics, so result streams don't wait forever
+    fn run<F>(&self, f: F) where F: FnOnce() {
+        if let Err(panic) = catch_unwind(AssertUnwindSafe(f)) {
+            let error = panic.downcast_ref::<&str>().map(|x| x.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "The search panicked".to_string());
+            self.update(|state| {
+                state.status = JobStatus::Failed;
+                state.error = Some(error);
+            });
+        }
+    }
+}
+
+enum Entry {
+    Path(PathBuf),
+    #[cfg(feature = "sql")]
+    Sql(SchematicNode),
+}
+
+/// An entry to search, the data of uncached SQL nodes is fetched in batches by `scheduled`
+enum Scheduled<'a> {
+    Entry(&'a Entry),
+    #[cfg(feature = "sql")]
+    Fetched(&'a SchematicNode, Result<Vec<u8>, String>),
+}
+
+struct CachedSchematic {
+    modified: Option<SystemTime>,
+    schematic: Arc<SpongeSchematic>,
+}
+
+pub struct SearchServer {
+    directory: Option<PathBuf>,
+    filter: PathFilter,
+    #[cfg(feature = "sql")]
+    database: Option<Arc<SchematicDatabase>>,
+    #[cfg(feature = "sql")]
+    loader: LoaderOptions,
+    patterns: Mutex<HashMap<String, Arc<SpongeSchematic>>>,
+    cache: Mutex<HashMap<String, CachedSchematic>>,
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+    next_id: AtomicUsize,
+}
+
+impl SearchServer {
+    pub fn new(directory: Option<PathBuf>, filter: PathFilter) -> SearchServer {
+        SearchServer {
+            directory,
+            filter,
+            #[cfg(feature = "sql")]
+            database: None,
+            #[cfg(feature = "sql")]
+            loader: LoaderOptions::default(),
+            patterns: Mutex::new(HashMap::new()),
+            cache: Mutex::new(HashMap::new()),
+            jobs: Mutex::new(HashMap::new()),
+            next_id: AtomicUsize::new(1),
+        }
+    }
+
+    #[cfg(feature = "sql")]
+    pub fn with_database(mut self, database: Option<Arc<SchematicDatabase>>, loader: LoaderOptions) -> SearchServer {
+        self.database = database;
+        self.loader = loader;
+        self
+    }
+
+    pub fn listen(self: Arc<Self>, server: Server) {
+        for request in server.incoming_requests() {
+            let search_server = self.clone();
+            std::thread::spawn(move || search_server.handle(request));
+        }
+    }
+
+    fn handle(self: Arc<Self>, mut request: Request) {
+        let url = request.url().to_string();
+        let path: Vec<&str> = url.split('?').next().unwrap().split('/').filter(|x| !x.is_empty()).collect();
+        let method = request.method().clone();
+        let result = match (&method, path.as_slice()) {
+            (Method::Post, ["patterns"]) => self.upload_pattern(&mut request),
+            (Method::Post, ["search"]) => self.start_search(&mut request),
+            (Method::Delete, ["patterns", id]) => self.patterns.lock().unwrap().remove(*id)
+                .map(|_| empty(204))
+                .ok_or_else(|| (404, format!("Unknown pattern '{}'", id))),
+            (Method::Delete, ["jobs", id]) => self.delete_job(id),
+            (Method::Get, ["jobs", id]) => self.job(id).map(|job| json(200, &*job.state())),
+            (Method::Get, ["jobs", id, "results"]) => match self.job(id) {
+                Ok(job) => {
+                    let stream = ResultStream { job, next: 0, buffer: Vec::new(), position: 0 };
+                    let _ = request.respond(Response::new(200.into(), vec![content_type("application/x-ndjson")], stream, None, None));
+                    return;
+                }
+                Err(e) => Err(e),
+            },
+            _ => Err((404, format!("No route for {} {}", method, url))),
+        };
+        let response = result.unwrap_or_else(|(status, error)| json(status, &ErrorResponse { error }));
+        let _ = request.respond(response);
+    }
+
+    fn next_id(&self, prefix: &str) -> String {
+        format!("{}{}", prefix, self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn job(&self, id: &str) -> Result<Arc<Job>, (u16, String)> {
+        self.jobs.lock().unwrap().get(id).cloned().ok_or_else(|| (404, format!("Unknown job '{}'", id)))
+    }
+
+    /// Removes a job with its matches, a running job is cancelled
+    fn delete_job(&self, id: &str) -> Result<Response<std::io::Cursor<Vec<u8>>>, (u16, String)> {
+        let job = self.jobs.lock().unwrap().remove(id).ok_or_else(|| (404, format!("Unknown job '{}'", id)))?;
+        job.update(|state| if state.status == JobStatus::Running {
+            state.status = JobStatus::Cancelled;
+        });
+        Ok(empty(204))
+    }
+
+    fn upload_pattern(&self, request: &mut Request) -> Result<Response<std::io::Cursor<Vec<u8>>>, (u16, String)> {
+        let mut data = Vec::new();
+        request.as_reader().read_to_end(&mut data).map_err(|e| (400, e.to_string()))?;
+        let pattern = SpongeSchematic::load_data(&mut data.as_slice()).map_err(|e| (400, format!("Invalid pattern: {}", e)))?;
+        let id = self.next_id("p");
+        self.patterns.lock().unwrap().insert(id.clone(), Arc::new(pattern));
+        Ok(json(201, &IdResponse { id }))
+    }
+
+    fn start_search(self: &Arc<Self>, request: &mut Request) -> Result<Response<std::io::Cursor<Vec<u8>>>, (u16, String)> {
+        let search_request: SearchRequest = serde_json::from_reader(request.as_reader()).map_err(|e| (400, format!("Invalid request: {}", e)))?;
+        let pattern = match (&search_request.pattern, &search_request.pattern_path) {
+            (Some(id), None) => self.patterns.lock().unwrap().get(id).cloned().ok_or_else(|| (404, format!("Unknown pattern '{}'", id)))?,
+            (None, Some(path)) => Arc::new(SpongeSchematic::load(&PathBuf::from(path)).map_err(|e| (400, format!("Invalid pattern: {}", e)))?),
+            _ => return Err((400, "Exactly one of 'pattern' and 'pattern_path' is required".to_string())),
+        };
+        let entries = self.entries(search_request.source).map_err(|e| (400, e))?;
+
+        let id = self.next_id("j");
+        let job = Arc::new(Job {
+            state: Mutex::new(JobState {
+                status: JobStatus::Running,
+                total: entries.len(),
+                searched: 0,
+                found: 0,
+                error: None,
+                matches: Vec::new(),
+            }),
+            changed: Condvar::new(),
+        });
+        self.jobs.lock().unwrap().insert(id.clone(), job.clone());
+
+        let search_server = self.clone();
+        std::thread::spawn(move || job.run(|| search_server.run_job(&job, entries, &pattern, &search_request)));
+        Ok(json(202, &IdResponse { id }))
+    }
+
+    fn entries(&self, source: Source) -> Result<Vec<Entry>, String> {
+        match source {
+            Source::Directory => match &self.directory {
+                Some(directory) => Ok(self.filter.collect(std::iter::once(directory.clone())).into_iter().map(Entry::Path).collect()),
+                None => Err("No directory configured".to_string()),
+            },
+            #[cfg(feature = "sql")]
+            Source::Sql => match &self.database {
+                Some(database) => {
+                    let mut seen = HashSet::new();
+                    Ok(block_on(database.load_all_schematics(&SchematicFilter::default()))?.into_iter()
+                        .filter(|node| seen.insert(node.id))
+                        .map(Entry::Sql)
+                        .collect())
+                }
+                None => Err("SQL is not enabled".to_string()),
+            },
+            #[cfg(not(feature = "sql"))]
+            Source::Sql => Err("SQL is not enabled".to_string()),
+        }
+    }
+
+    fn run_job(&self, job: &Job, entries: Vec<Entry>, pattern: &SpongeSchematic, request: &SearchRequest) {
+        let search_behavior = SearchBehavior {
+            ignore_block_data: request.ignore_data,
+            ignore_block_entities: true,
+            ignore_air: request.ignore_air,
+            air_as_any: request.air_as_any,
+            ignore_entities: true,
+            threshold: request.threshold,
+            invalid_nbt: false,
+        };
+        self.scheduled(&entries).for_each(|scheduled| {
+            let skip = {
+                let state = job.state();
+                state.status == JobStatus::Cancelled || (request.limit != 0 && state.found >= request.limit)
+            };
+            if skip {
+                job.update(|state| state.searched += 1);
+                return;
+            }
+            let (name, schematic) = match self.load(scheduled) {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("Error while loading schematic: {}", e);
+                    job.update(|state| state.searched += 1);
+                    return;
+                }
+            };
+            let matches = search((*schematic).clone(), pattern, search_behavior);
+            job.update(|state| {
+                state.searched += 1;
+                for match_ in matches {
+                    if request.limit != 0 && state.matches.len() >= request.limit {
+                        break;
+                    }
+                    state.matches.push(FoundEvent { name: name.clone(), match_ });
+                }
+                state.found = state.matches.len();
+            });
+        });
+        job.update(|state| if state.status == JobStatus::Running {
+            state.status = JobStatus::Done;
+        });
+    }
+
+    #[cfg(not(feature = "sql"))]
+    fn scheduled<'a>(&'a self, entries: &'a [Entry]) -> impl ParallelIterator<Item = Scheduled<'a>> {
+        entries.par_iter().map(Scheduled::Entry)
+    }
+
+    /// Like `par_schematics`, SQL nodes that aren't cached yet are streamed from the database by a loader thread
+    #[cfg(feature = "sql")]
+    fn scheduled<'a>(&'a self, entries: &'a [Entry]) -> impl ParallelIterator<Item = Scheduled<'a>> {
+        let mut ids = Vec::new();
+        let mut nodes = HashMap::new();
+        let mut rest = Vec::new();
+        let cache = self.cache.lock().unwrap();
+        for entry in entries {
+            match entry {
+                Entry::Sql(node) if !cache.contains_key(&format!("sql:{}", node.id)) => {
+                    ids.push(node.id);
+                    nodes.insert(node.id, node);
+                }
+                _ => rest.push(entry),
+            }
+        }
+        drop(cache);
+        let loader = self.database.as_ref()
+            .filter(|_| !ids.is_empty())
+            .map(|database| database.stream_schemdata(ids, self.loader));
+        let fetched = loader.into_iter().flatten().par_bridge().map(move |(id, data)| Scheduled::Fetched(nodes[&id], data));
+        rest.into_par_iter().map(Scheduled::Entry).chain(fetched)
+    }
+
+    /// Loads a schematic through the cache, files are reloaded when they were modified
+    #[cfg_attr(not(feature = "sql"), allow(clippy::infallible_destructuring_match))]
+    fn load(&self, scheduled: Scheduled) -> Result<(String, Arc<SpongeSchematic>), String> {
+        let entry = match scheduled {
+            Scheduled::Entry(entry) => entry,
+            #[cfg(feature = "sql")]
+            Scheduled::Fetched(node, data) => {
+                let name = format!("{} ({})", node.name, node.id);
+                let schematic = Arc::new(SpongeSchematic::load_data(&mut data.map_err(|e| format!("{}: {}", name, e))?.as_slice()).map_err(|e| format!("{}: {}", name, e))?);
+                self.cache.lock().unwrap().insert(format!("sql:{}", node.id), CachedSchematic { modified: None, schematic: schematic.clone() });
+                return Ok((name, schematic));
+            }
+        };
+        let (key, name, modified) = match entry {
+            Entry::Path(path) => (
+                format!("path:{}", path.display()),
+                path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+                std::fs::metadata(path).and_then(|x| x.modified()).ok(),
+            ),
+            #[cfg(feature = "sql")]
+            Entry::Sql(node) => (format!("sql:{}", node.id), format!("{} ({})", node.name, node.id), None),
+        };
+        if let Some(cached) = self.cache.lock().unwrap().get(&key) {
+            if cached.modified == modified {
+                return Ok((name, cached.schematic.clone()));
+            }
+        }
+        let schematic = Arc::new(match entry {
+            Entry::Path(path) => SpongeSchematic::load(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+            #[cfg(feature = "sql")]
+            Entry::Sql(_) => return Err(format!("{}: not fetched from the database", name)),
+        });
+        self.cache.lock().unwrap().insert(key, CachedSchematic { modified, schematic: schematic.clone() });
+        Ok((name, schematic))
+    }
+}
+
+/// Streams the matches of a job as JSON lines until it is finished
+struct ResultStream {
+    job: Arc<Job>,
+    next: usize,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ResultStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.buffer.len() {
+            self.buffer.clear();
+            self.position = 0;
+            let mut state = self.job.state();
+            while self.next >= state.matches.len() && state.status == JobStatus::Running {
+                state = self.job.changed.wait(state).unwrap_or_else(PoisonError::into_inner);
+            }
+            for found in state.matches[self.next..].iter() {
+                serde_json::to_writer(&mut self.buffer, found)?;
+                self.buffer.push(b'\n');
+            }
+            self.next = state.matches.len();
+        }
+        let length = buf.len().min(self.buffer.len() - self.position);
+        buf[..length].copy_from_slice(&self.buffer[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+#[derive(Serialize)]
+struct IdResponse {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).unwrap()
+}
+
+fn empty(status: u16) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_data(Vec::new()).with_status_code(status)
+}
+
+fn json<T: Serialize>(status: u16, value: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_data(serde_json::to_vec(value).unwrap())
+        .with_status_code(status)
+        .with_header(content_type("application/json"))
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+    use super::*;
+
+    fn start() -> String {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let search_server = Arc::new(SearchServer::new(Some(PathBuf::from("../tests")), PathFilter::default()));
+        std::thread::spawn(move || search_server.listen(server));
+        format!("http://{}", address)
+    }
+
+    fn id(response: ureq::Response) -> String {
+        let value: serde_json::Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        value["id"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_search() {
+        let url = start();
+        let pattern = std::fs::read("../tests/endstone.schem").unwrap();
+        let pattern = id(ureq::post(&format!("{}/patterns", url)).send_bytes(&pattern).unwrap());
+
+        for _ in 0..2 {
+            let job = id(ureq::post(&format!("{}/search", url))
+                .set("Content-Type", "application/json")
+                .send_string(&format!(r#"{{"pattern": "{}", "limit": 3}}"#, pattern))
+                .unwrap());
+
+            let results = ureq::get(&format!("{}/jobs/{}/results", url, job)).call().unwrap();
+            let lines: Vec<serde_json::Value> = std::io::BufReader::new(results.into_reader()).lines()
+                .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+                .collect();
+            assert_eq!(lines.len(), 3);
+            assert!(lines.iter().all(|x| x["percent"].as_f64().unwrap() >= 0.9));
+
+            let status: serde_json::Value = serde_json::from_str(&ureq::get(&format!("{}/jobs/{}", url, job)).call().unwrap().into_string().unwrap()).unwrap();
+            assert_eq!(status["status"], "done");
+            assert_eq!(status["found"], 3);
+            assert_eq!(status["searched"], status["total"]);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let url = start();
+
+        match ureq::get(&format!("{}/jobs/j42", url)).call() {
+            Err(ureq::Error::Status(status, _)) => assert_eq!(status, 404),
+            _ => panic!("expected 404"),
+        }
+        match ureq::post(&format!("{}/patterns", url)).send_bytes(b"not a schematic") {
+            Err(ureq::Error::Status(status, _)) => assert_eq!(status, 400),
+            _ => panic!("expected 400"),
+        }
+        match ureq::post(&format!("{}/search", url)).send_string(r#"{"pattern": "p42"}"#) {
+            Err(ureq::Error::Status(status, _)) => assert_eq!(status, 404),
+            _ => panic!("expected 404"),
+        }
+    }
+
+    #[test]
+    fn test_delete() {
+        let url = start();
+        let pattern = std::fs::read("../tests/endstone.schem").unwrap();
+        let pattern = id(ureq::post(&format!("{}/patterns", url)).send_bytes(&pattern).unwrap());
+        let job = id(ureq::post(&format!("{}/search", url))
+            .send_string(&format!(r#"{{"pattern": "{}"}}"#, pattern))
+            .unwrap());
+
+        assert_eq!(ureq::delete(&format!("{}/jobs/{}", url, job)).call().unwrap().status(), 204);
+        assert_eq!(ureq::delete(&format!("{}/patterns/{}", url, pattern)).call().unwrap().status(), 204);
+        for path in [format!("jobs/{}", job), format!("patterns/{}", pattern)] {
+            match ureq::delete(&format!("{}/{}", url, path)).call() {
+                Err(ureq::Error::Status(status, _)) => assert_eq!(status, 404),
+                _ => panic!("expected 404"),
+            }
+        }
+        match ureq::post(&format!("{}/search", url)).send_string(&format!(r#"{{"pattern": "{}"}}"#, pattern)) {
+            Err(ureq::Error::Status(status, _)) => assert_eq!(status, 404),
+            _ => panic!("expected 404"),
+        }
+    }
+
+    #[test]
+    fn test_failed_job() {
+        let job = Job {
+            state: Mutex::new(JobState { status: JobStatus::Running, total: 1, searched: 0, found: 0, error: None, matches: Vec::new() }),
+            changed: Condvar::new(),
+        };
+        job.run(|| job.update(|_| panic!("broken schematic")));
+        let mut stream = ResultStream { job: Arc::new(job), next: 0, buffer: Vec::new(), position: 0 };
+
+        let mut results = String::new();
+        stream.read_to_string(&mut results).unwrap();
+        assert!(results.is_empty());
+        let state = stream.job.state();
+        assert_eq!(state.status, JobStatus::Failed);
+        assert_eq!(state.error.as_deref(), Some("broken schematic"));
+    }
+}
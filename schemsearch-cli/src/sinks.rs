@@ -34,6 +34,35 @@ impl FromStr for OutputFormat {
     }
 }
 
+/// A `-o` argument, `format:path` or `sql:table`
+#[derive(Debug, Clone)]
+pub enum Output {
+    Stream(OutputFormat, OutputSink),
+    #[cfg(feature = "sql")]
+    Sql(String),
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+        let format = match split.next() {
+            None => return Err("No format specified".to_string()),
+            Some(x) => x
+        };
+        let path = match split.next() {
+            None => return Err("No path specified".to_string()),
+            Some(x) => x
+        };
+        #[cfg(feature = "sql")]
+        if format == "sql" {
+            return Ok(Output::Sql(path.to_string()));
+        }
+        Ok(Output::Stream(OutputFormat::from_str(format)?, OutputSink::from_str(path)?))
+    }
+}
+
 impl FromStr for OutputSink {
     type Err = String;
 
@@ -59,8 +88,10 @@ impl OutputSink {
 impl OutputFormat {
     pub fn found_match(&self, name: &String, pos: Match) -> String {
         match self {
-            OutputFormat::Text => format!("Found match in '{}' at x: {}, y: {}, z: {}, % = {}\n", name, pos.x, pos.y, pos.z, pos.percent),
-            OutputFormat::CSV => format!("{},{},{},{},{}\n", name, pos.x, pos.y, pos.z, pos.percent),
+            OutputFormat::Text => format!("Found match in '{}' at x: {}, y: {}, z: {} (world x: {}, y: {}, z: {}, bounding box: {:?} to {:?}), % = {}\n", name, pos.x, pos.y, pos.z, pos.world_x, pos.world_y, pos.world_z, pos.bounding_box.min, pos.bounding_box.max, pos.percent),
+            OutputFormat::CSV => format!("{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n", name, pos.x, pos.y, pos.z, pos.percent, pos.world_x, pos.world_y, pos.world_z,
+                                         pos.bounding_box.min[0], pos.bounding_box.min[1], pos.bounding_box.min[2],
+                                         pos.bounding_box.max[0], pos.bounding_box.max[1], pos.bounding_box.max[2]),
             OutputFormat::JSON => format!("{}\n", serde_json::to_string(&JsonEvent::Found(FoundEvent {
                 name: name.clone(),
                 match_: pos,
@@ -71,7 +102,7 @@ impl OutputFormat {
     pub fn start(&self, total: u32, search_behavior: &SearchBehavior, start_time: u128) -> String {
         match self {
             OutputFormat::Text => format!("Starting search in {} schematics\n", total),
-            OutputFormat::CSV => "Name,X,Y,Z,Percent\n".to_owned(),
+            OutputFormat::CSV => "Name,X,Y,Z,Percent,WorldX,WorldY,WorldZ,MinX,MinY,MinZ,MaxX,MaxY,MaxZ\n".to_owned(),
             OutputFormat::JSON => format!("{}\n", serde_json::to_string(&JsonEvent::Init(InitEvent {
                 total,
                 search_behavior: search_behavior.clone(),
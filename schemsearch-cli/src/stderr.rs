@@ -1,6 +1,6 @@
 use std::fmt::Debug;
 use std::io::Write;
-use indicatif::TermLike;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle, TermLike};
 
 #[derive(Debug)]
 pub struct MaschineStdErr { pub(crate) size: u16}
@@ -41,4 +41,13 @@ impl TermLike for MaschineStdErr {
     fn flush(&self) -> std::io::Result<()> {
         std::io::stderr().flush()
     }
-}
\ No newline at end of file
+}
+
+pub fn progress_bar(len: u64, term_size: u16) -> ProgressBar {
+    let bar = ProgressBar::new(len); // "maschine"
+    bar.set_style(ProgressStyle::with_template("[{elapsed}, ETA: {eta}] {wide_bar} {pos}/{len} {per_sec}").unwrap());
+    if term_size != 0 {
+        bar.set_draw_target(ProgressDrawTarget::term_like(Box::new(MaschineStdErr { size: term_size })))
+    }
+    bar
+}
@@ -18,19 +18,88 @@
 #[cfg(feature = "sql")]
 use std::io::Cursor;
 use std::path::PathBuf;
+use std::sync::Arc;
+use schemsearch_files::SpongeSchematic;
+use schemsearch_index::SchematicIndex;
+use crate::archive::{ArchiveEntrySupplier, TarSchematicSupplier};
 #[cfg(feature = "sql")]
 use futures::executor::block_on;
 #[cfg(feature = "sql")]
-use schemsearch_files::SpongeSchematic;
-#[cfg(feature = "sql")]
-use schemsearch_sql::{load_schemdata, SchematicNode};
+use schemsearch_sql::{SchematicDatabase, SchematicNode};
 
 pub enum SchematicSupplierType {
     PATH(PathSchematicSupplier),
+    INDEX(IndexSchematicSupplier),
+    ARCHIVE(ArchiveEntrySupplier),
+    TAR(TarSchematicSupplier),
+    STDIN(StdinSchematicSupplier),
     #[cfg(feature = "sql")]
     SQL(SqlSchematicSupplier),
 }
 
+impl SchematicSupplierType {
+    pub fn for_each_schematic<F>(&self, mut f: F) where F: FnMut(String, Result<SpongeSchematic, String>) {
+        match self {
+            SchematicSupplierType::PATH(schem) => f(schem.get_name(), SpongeSchematic::load(&schem.path)),
+            SchematicSupplierType::INDEX(schem) => f(schem.get_name(), schem.get_schematic()),
+            SchematicSupplierType::ARCHIVE(schem) => f(schem.get_name(), schem.get_schematic()),
+            SchematicSupplierType::STDIN(schem) => f(schem.get_name(), schem.get_schematic()),
+            SchematicSupplierType::TAR(schem) => {
+                if let Err(e) = schem.for_each_schematic(&mut f) {
+                    f(schem.get_name(), Err(e));
+                }
+            }
+            #[cfg(feature = "sql")]
+            SchematicSupplierType::SQL(schem) => f(schem.get_name(), schem.get_schematic()),
+        }
+    }
+
+    /// The number of schematics `for_each_schematic` yields
+    pub fn count(&self) -> usize {
+        match self {
+            SchematicSupplierType::TAR(schem) => schem.entries,
+            _ => 1,
+        }
+    }
+
+    #[cfg(feature = "sql")]
+    pub fn node_id(&self) -> Option<i32> {
+        match self {
+            SchematicSupplierType::SQL(schem) => Some(schem.node.id),
+            _ => None,
+        }
+    }
+}
+
+/// A supplier handed to the workers by `par_schematics`
+pub enum ScheduledSchematic<'a> {
+    Supplier(&'a SchematicSupplierType),
+    /// A database schematic with the data fetched by the loader
+    #[cfg(feature = "sql")]
+    Fetched(&'a SqlSchematicSupplier, Result<Vec<u8>, String>),
+}
+
+impl ScheduledSchematic<'_> {
+    pub fn for_each_schematic<F>(self, f: F) where F: FnMut(String, Result<SpongeSchematic, String>) {
+        match self {
+            ScheduledSchematic::Supplier(schem) => schem.for_each_schematic(f),
+            #[cfg(feature = "sql")]
+            ScheduledSchematic::Fetched(schem, data) => {
+                let mut f = f;
+                f(schem.get_name(), data.and_then(|data| SqlSchematicSupplier::parse(&data)))
+            }
+        }
+    }
+
+    #[cfg(feature = "sql")]
+    pub fn node_id(&self) -> Option<i32> {
+        match self {
+            ScheduledSchematic::Supplier(schem) => schem.node_id(),
+            ScheduledSchematic::Fetched(schem, _) => Some(schem.node.id),
+        }
+    }
+}
+
 pub trait SchematicSupplier {
     fn get_name(&self) -> String;
 }
@@ -41,20 +110,61 @@ pub struct PathSchematicSupplier {
 
 impl SchematicSupplier for PathSchematicSupplier {
     fn get_name(&self) -> String {
-        self.path.file_stem().unwrap().to_str().unwrap().to_string()
+        self.path.file_stem().unwrap_or_default().to_string_lossy().to_string()
+    }
+}
+
+pub struct StdinSchematicSupplier {
+    pub data: Vec<u8>,
+}
+
+impl StdinSchematicSupplier {
+    pub fn get_schematic(&self) -> Result<SpongeSchematic, String> {
+        SpongeSchematic::load_data(&mut self.data.as_slice())
+    }
+}
+
+impl SchematicSupplier for StdinSchematicSupplier {
+    fn get_name(&self) -> String {
+        "stdin".to_string()
+    }
+}
+
+pub struct IndexSchematicSupplier {
+    pub index: Arc<SchematicIndex>,
+    pub entry: usize,
+}
+
+impl IndexSchematicSupplier {
+    pub fn fits(&self, pattern: &SpongeSchematic) -> bool {
+        self.index.entries[self.entry].fits(pattern)
+    }
+
+    pub fn get_schematic(&self) -> Result<SpongeSchematic, String> {
+        self.index.load_schematic(&self.index.entries[self.entry])
+    }
+}
+
+impl SchematicSupplier for IndexSchematicSupplier {
+    fn get_name(&self) -> String {
+        self.index.entries[self.entry].name.clone()
     }
 }
 
 #[cfg(feature = "sql")]
 pub struct SqlSchematicSupplier {
+    pub database: Arc<SchematicDatabase>,
     pub node: SchematicNode,
 }
 
 #[cfg(feature = "sql")]
 impl SqlSchematicSupplier {
     pub fn get_schematic(&self) -> Result<SpongeSchematic, String> {
-        let mut schemdata = block_on(load_schemdata(self.node.id));
-        SpongeSchematic::load_data(&mut Cursor::new(schemdata.as_mut_slice()))
+        SqlSchematicSupplier::parse(&block_on(self.database.load_schemdata(self.node.id))?)
+    }
+
+    fn parse(schemdata: &[u8]) -> Result<SpongeSchematic, String> {
+        SpongeSchematic::load_data(&mut Cursor::new(schemdata))
     }
 }
 
@@ -64,5 +174,3 @@ impl SchematicSupplier for SqlSchematicSupplier {
         format!("{} ({})", self.node.name, self.node.id)
     }
 }
-
-
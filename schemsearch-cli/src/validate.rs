@@ -0,0 +1,92 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::path::{Path, PathBuf};
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use clap::error::ErrorKind;
+use rayon::prelude::*;
+use schemsearch_lib::validation::{Rules, ValidationReport, Validator, Violation};
+use crate::input::{check_stdin, collect_schematics, input_args, par_schematics, progress, worker_args};
+use crate::types::SchematicSupplierType;
+
+pub fn validate_command() -> Command {
+    worker_args(input_args(Command::new("validate")))
+        .about("Checks schematics for invalid NBT data, structural errors and the rules of a rule file")
+        .arg(
+            Arg::new("rules")
+                .help("A TOML or YAML file with the rules to check")
+                .long("rules")
+                .value_hint(ValueHint::FilePath)
+                .action(ArgAction::Set),
+        )
+}
+
+pub fn run_validate(cmd: &mut Command, matches: &ArgMatches) {
+    check_stdin(cmd, matches, &["schematic"]);
+
+    let validator = match matches.get_one::<String>("rules") {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            let rules = match Rules::load(&path) {
+                Ok(x) => x,
+                Err(e) => cmd.error(ErrorKind::InvalidValue, format!("Error while loading rules ({}): {}", path.display(), e)).exit(),
+            };
+            Validator::new(rules, path.parent().unwrap_or(Path::new("")))
+        }
+        None => Validator::new(Rules::default(), Path::new("")),
+    };
+    let validator = match validator {
+        Ok(x) => x,
+        Err(e) => cmd.error(ErrorKind::InvalidValue, format!("Error while loading rules: {}", e)).exit(),
+    };
+
+    let schematics = collect_schematics(cmd, matches, None);
+    let bar = progress(matches, schematics.iter().map(SchematicSupplierType::count).sum());
+
+    let mut results: Vec<(usize, String, ValidationReport)> = par_schematics(matches, &schematics).flat_map_iter(|(index, schem)| {
+        let mut results = Vec::new();
+        schem.for_each_schematic(|name, schematic| {
+            match schematic {
+                Ok(schematic) => results.push((index, name, validator.validate(&schematic))),
+                Err(e) => results.push((index, name, ValidationReport {
+                    violations: vec![Violation { rule: "load".to_string(), message: format!("could not be loaded: {}", e) }],
+                })),
+            }
+            bar.inc(1);
+        });
+        results
+    }).collect();
+    results.sort_by_key(|(index, _, _)| *index);
+
+    let mut failed = 0;
+    for (_, name, report) in results.iter() {
+        if report.passed() {
+            println!("{}: passed", name);
+            continue;
+        }
+        failed += 1;
+        println!("{}: failed", name);
+        for violation in report.violations.iter() {
+            println!("  [{}] {}", violation.rule, violation.message);
+        }
+    }
+    println!("{} of {} schematics failed", failed, results.len());
+
+    if failed != 0 {
+        std::process::exit(1);
+    }
+}
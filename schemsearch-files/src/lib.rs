@@ -16,10 +16,12 @@
  */
 
 use std::collections::hash_map::HashMap;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 use nbt::{CompoundTag, Tag};
 
+pub mod upgrade;
+
 #[derive(Clone, Debug)]
 pub struct SpongeSchematic {
     pub data_version: i32,
@@ -63,7 +65,10 @@ pub struct Entity {
 
 impl SpongeSchematic {
     pub fn load_data<R>(data: &mut R) -> Result<SpongeSchematic, String> where R: Read {
-        let nbt: CompoundTag = nbt::decode::read_gzip_compound_tag(data).map_err(|e| e.to_string())?;
+        let mut nbt: CompoundTag = nbt::decode::read_gzip_compound_tag(data).map_err(|e| e.to_string())?;
+        if let Ok(schematic) = nbt.get_compound_tag("Schematic") {
+            nbt = schematic.clone();
+        }
         let version = nbt.get_i32("Version").unwrap_or_else(|_| {
             return if nbt.contains_key("Blocks") {
                 3
@@ -89,6 +94,75 @@ impl SpongeSchematic {
         Self::load_data(&mut file)
     }
 
+    pub fn save_data<W>(&self, data: &mut W, version: i32) -> Result<(), String> where W: Write {
+        let nbt = match version {
+            2 => self.to_nbt_2(),
+            3 => self.to_nbt_3(),
+            _ => return Err(format!("Can't write schematic version {}", version)),
+        };
+        nbt::encode::write_gzip_compound_tag(data, &nbt).map_err(|e| e.to_string())
+    }
+
+    pub fn save(&self, path: &PathBuf, version: i32) -> Result<(), String> {
+        let mut file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+        self.save_data(&mut file, version)
+    }
+
+    pub fn to_nbt_2(&self) -> CompoundTag {
+        let mut nbt = CompoundTag::named("Schematic");
+        nbt.insert_i32("Version", 2);
+        nbt.insert_i32("DataVersion", self.data_version);
+        nbt.insert_compound_tag("Metadata", self.metadata.clone());
+        nbt.insert_i16("Width", self.width as i16);
+        nbt.insert_i16("Height", self.height as i16);
+        nbt.insert_i16("Length", self.length as i16);
+        nbt.insert_i32_vec("Offset", self.offset.to_vec());
+        nbt.insert_i32("PaletteMax", self.palette_max);
+        let mut palette = CompoundTag::new();
+        for (key, value) in self.palette.iter() {
+            palette.insert_i32(key, *value);
+        }
+        nbt.insert_compound_tag("Palette", palette);
+        nbt.insert_i8_vec("BlockData", write_varint_array(&self.block_data));
+        nbt.insert_compound_tag_vec("BlockEntities", self.block_entities.iter().map(|e| {
+            let mut tag = CompoundTag::new();
+            tag.insert_str("Id", &e.id);
+            tag.insert_i32_vec("Pos", e.pos.to_vec());
+            tag
+        }));
+        nbt
+    }
+
+    pub fn to_nbt_3(&self) -> CompoundTag {
+        let mut schematic = CompoundTag::new();
+        schematic.insert_i32("Version", 3);
+        schematic.insert_i32("DataVersion", self.data_version);
+        schematic.insert_compound_tag("Metadata", self.metadata.clone());
+        schematic.insert_i16("Width", self.width as i16);
+        schematic.insert_i16("Height", self.height as i16);
+        schematic.insert_i16("Length", self.length as i16);
+        schematic.insert_i32_vec("Offset", self.offset.to_vec());
+        let mut palette = CompoundTag::new();
+        for (key, value) in self.palette.iter() {
+            palette.insert_i32(key, *value);
+        }
+        let mut blocks = CompoundTag::new();
+        blocks.insert_compound_tag("Palette", palette);
+        blocks.insert_i8_vec("Data", write_varint_array(&self.block_data));
+        blocks.insert_compound_tag_vec("BlockEntities", self.block_entities.iter().map(|e| {
+            let mut tag = CompoundTag::new();
+            tag.insert_str("Id", &e.id);
+            tag.insert_i32_vec("Pos", e.pos.to_vec());
+            tag.insert_compound_tag("Data", CompoundTag::new());
+            tag
+        }));
+        schematic.insert_compound_tag("Blocks", blocks);
+
+        let mut nbt = CompoundTag::new();
+        nbt.insert_compound_tag("Schematic", schematic);
+        nbt
+    }
+
     pub fn from_nbt_1(nbt: CompoundTag) -> Result<Self, String> {
         Ok(Self {
             data_version: 0,
@@ -132,12 +206,20 @@ impl SpongeSchematic {
             offset: read_offset(nbt.get_i32_vec("Offset").map_err(|e| e.to_string())?)?,
             palette_max: compute_palette_max(blocks.get_compound_tag("Palette").map_err(|e| e.to_string())?),
             palette: read_palette(blocks.get_compound_tag("Palette").map_err(|e| e.to_string())?),
-            block_data: read_blocks(blocks.get_i8_vec("BlockData").map_err(|e| e.to_string())?),
+            block_data: read_blocks(blocks.get_i8_vec("Data").or_else(|_| blocks.get_i8_vec("BlockData")).map_err(|e| e.to_string())?),
             block_entities: read_tile_entities(blocks.get_compound_tag_vec("BlockEntities").unwrap_or_else(|_| vec![]))?,
             entities: None,
         })
     }
 
+    /// The position of the minimum corner relative to the point WorldEdit pastes the schematic at, from `WEOffset` in the metadata.
+    /// Without it WorldEdit pastes the minimum corner at that point. `offset` isn't used, WorldEdit stores the position it was copied from there.
+    pub fn origin(&self) -> [i32; 3] {
+        match (self.metadata.get_i32("WEOffsetX"), self.metadata.get_i32("WEOffsetY"), self.metadata.get_i32("WEOffsetZ")) {
+            (Ok(x), Ok(y), Ok(z)) => [x, y, z],
+            _ => [0; 3],
+        }
+    }
 }
 
 fn read_tile_entities(tag: Vec<&CompoundTag>) -> Result<Vec<BlockEntity>, String> {
@@ -176,7 +258,7 @@ fn compute_palette_max(palette: &CompoundTag) -> i32 {
     palette.iter().map(|(_, v)| v).filter_map(|v| match v {
         Tag::Int(n) => Some(*n),
         _ => None,
-    }).max().unwrap_or(0)
+    }).max().map(|n| n + 1).unwrap_or(0)
 }
 
 #[inline]
@@ -213,3 +295,20 @@ pub fn read_varint_array(read: &Vec<i8>) -> Vec<i32> {
     }
     data
 }
+
+#[inline]
+pub fn write_varint_array(data: &[i32]) -> Vec<i8> {
+    let mut write = Vec::with_capacity(data.len());
+    for value in data {
+        let mut value = *value as u32;
+        loop {
+            if value & !0x7F == 0 {
+                write.push(value as u8 as i8);
+                break;
+            }
+            write.push(((value & 0x7F) | 0x80) as u8 as i8);
+            value >>= 7;
+        }
+    }
+    write
+}
@@ -0,0 +1,182 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use crate::SpongeSchematic;
+
+type Fix = fn(&str, &mut Vec<(String, String)>) -> Option<&'static str>;
+
+/// Block fixes keyed by the data version of the release that introduced them
+const FIXES: [(i32, Fix); 4] = [
+    (1952, fix_1_14),
+    (2566, fix_1_16),
+    (2724, fix_1_17),
+    (3698, fix_1_20_3),
+];
+
+fn fix_1_14(id: &str, _: &mut Vec<(String, String)>) -> Option<&'static str> {
+    match id {
+        "minecraft:sign" => Some("minecraft:oak_sign"),
+        "minecraft:wall_sign" => Some("minecraft:oak_wall_sign"),
+        "minecraft:stone_slab" => Some("minecraft:smooth_stone_slab"),
+        _ => None,
+    }
+}
+
+#[allow(clippy::ptr_arg)]
+fn fix_1_16(id: &str, properties: &mut Vec<(String, String)>) -> Option<&'static str> {
+    if id.ends_with("_wall") {
+        for (key, value) in properties.iter_mut() {
+            if matches!(key.as_str(), "north" | "east" | "south" | "west") {
+                match value.as_str() {
+                    "true" => *value = "low".to_string(),
+                    "false" => *value = "none".to_string(),
+                    _ => {}
+                }
+            }
+        }
+    }
+    None
+}
+
+fn fix_1_17(id: &str, properties: &mut Vec<(String, String)>) -> Option<&'static str> {
+    match id {
+        "minecraft:grass_path" => Some("minecraft:dirt_path"),
+        "minecraft:cauldron" => {
+            let level = properties.iter().position(|(key, _)| key == "level")?;
+            if properties[level].1 == "0" {
+                properties.remove(level);
+                None
+            } else {
+                Some("minecraft:water_cauldron")
+            }
+        }
+        _ => None,
+    }
+}
+
+fn fix_1_20_3(id: &str, _: &mut Vec<(String, String)>) -> Option<&'static str> {
+    match id {
+        "minecraft:grass" => Some("minecraft:short_grass"),
+        _ => None,
+    }
+}
+
+/// Upgrades a block state like `minecraft:grass_path` saved in `from` to its name in `to`
+pub fn upgrade_block(block: &str, from: i32, to: i32) -> String {
+    if from >= to {
+        return block.to_string();
+    }
+    let (id, properties) = match block.split_once('[') {
+        Some((id, properties)) => (id, properties.trim_end_matches(']')),
+        None => (block, ""),
+    };
+    let mut id = id.to_string();
+    let mut properties: Vec<(String, String)> = properties.split(',')
+        .filter_map(|x| x.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+    for (data_version, fix) in FIXES {
+        if from < data_version && data_version <= to {
+            if let Some(renamed) = fix(&id, &mut properties) {
+                id = renamed.to_string();
+            }
+        }
+    }
+
+    if properties.is_empty() {
+        id
+    } else {
+        let properties: Vec<String> = properties.into_iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        format!("{}[{}]", id, properties.join(","))
+    }
+}
+
+impl SpongeSchematic {
+    /// Renames the palette entries to their names in `data_version`, schematics that are newer are left as they are
+    pub fn upgrade(&mut self, data_version: i32) {
+        if self.data_version >= data_version {
+            return;
+        }
+        let mut palette: HashMap<String, i32> = HashMap::with_capacity(self.palette.len());
+        let mut remap: HashMap<i32, i32> = HashMap::new();
+        for (name, id) in self.palette.iter() {
+            let name = upgrade_block(name, self.data_version, data_version);
+            match palette.get(&name) {
+                Some(existing) => { remap.insert(*id, *existing); }
+                None => { palette.insert(name, *id); }
+            }
+        }
+        if !remap.is_empty() {
+            for block in self.block_data.iter_mut() {
+                if let Some(id) = remap.get(block) {
+                    *block = *id;
+                }
+            }
+        }
+        self.palette = palette;
+        self.data_version = data_version;
+    }
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use nbt::CompoundTag;
+    use super::*;
+
+    #[test]
+    fn test_upgrade_block() {
+        assert_eq!(upgrade_block("minecraft:grass_path", 2586, 3700), "minecraft:dirt_path");
+        assert_eq!(upgrade_block("minecraft:grass", 2230, 3700), "minecraft:short_grass");
+        assert_eq!(upgrade_block("minecraft:grass", 2230, 3465), "minecraft:grass");
+        assert_eq!(upgrade_block("minecraft:wall_sign[facing=north]", 1631, 3700), "minecraft:oak_wall_sign[facing=north]");
+        assert_eq!(upgrade_block("minecraft:cobblestone_wall[east=true,north=false,up=true]", 2230, 2586), "minecraft:cobblestone_wall[east=low,north=none,up=true]");
+        assert_eq!(upgrade_block("minecraft:cauldron[level=2]", 2586, 2730), "minecraft:water_cauldron[level=2]");
+        assert_eq!(upgrade_block("minecraft:cauldron[level=0]", 2586, 2730), "minecraft:cauldron");
+        assert_eq!(upgrade_block("minecraft:stone_slab[type=top]", 3465, 3700), "minecraft:stone_slab[type=top]");
+    }
+
+    #[test]
+    fn test_upgrade_schematic() {
+        let mut schem = SpongeSchematic {
+            data_version: 2230,
+            metadata: CompoundTag::new(),
+            width: 3,
+            height: 1,
+            length: 1,
+            offset: [0, 0, 0],
+            palette_max: 3,
+            palette: vec![
+                ("minecraft:grass_path".to_owned(), 0),
+                ("minecraft:cauldron[level=0]".to_owned(), 1),
+                ("minecraft:cauldron".to_owned(), 2),
+            ].into_iter().collect(),
+            block_data: vec![0, 1, 2],
+            block_entities: vec![],
+            entities: None,
+        };
+        schem.upgrade(3700);
+
+        assert_eq!(schem.data_version, 3700);
+        assert_eq!(schem.palette.len(), 2);
+        assert_eq!(schem.palette.get("minecraft:dirt_path"), Some(&0));
+        let cauldron = *schem.palette.get("minecraft:cauldron").unwrap();
+        assert_eq!(schem.block_data, vec![0, cauldron, cauldron]);
+    }
+}
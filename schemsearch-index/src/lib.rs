@@ -0,0 +1,507 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use nbt::CompoundTag;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use schemsearch_files::{read_varint_array, write_varint_array, BlockEntity, SpongeSchematic};
+use schemsearch_lib::similarity::{fingerprint, Fingerprint};
+use schemsearch_lib::SearchBehavior;
+use crate::neighbourhood::{neighbourhoods, MAX_BROKEN_PER_BLOCK};
+
+mod neighbourhood;
+
+const MAGIC: &[u8; 8] = b"SCHIDX03";
+const CHUNK_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub path: String,
+    pub name: String,
+    pub modified: u128,
+    pub size: u64,
+    pub hash: u64,
+    pub data_version: i32,
+    pub width: u16,
+    pub height: u16,
+    pub length: u16,
+    pub offset: [i32; 3],
+    pub origin: [i32; 3],
+    pub fingerprint: Fingerprint,
+    data_position: u64,
+    data_length: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedBlocks {
+    palette: Vec<String>,
+    block_data: Vec<i8>,
+    block_entities: Vec<(String, [i32; 3])>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct IndexStats {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub errors: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SchematicIndex {
+    path: PathBuf,
+    postings_position: u64,
+    pub entries: Vec<IndexEntry>,
+}
+
+enum IndexedFile {
+    Unchanged(IndexEntry, Vec<u8>, Vec<u32>),
+    Added(IndexEntry, Vec<u8>, Vec<u32>),
+    Updated(IndexEntry, Vec<u8>, Vec<u32>),
+}
+
+impl IndexEntry {
+    pub fn fits(&self, pattern: &SpongeSchematic) -> bool {
+        self.width >= pattern.width && self.height >= pattern.height && self.length >= pattern.length
+    }
+}
+
+impl SchematicIndex {
+    pub fn open(path: &Path) -> Result<SchematicIndex, String> {
+        SchematicIndex::read(path)?.ok_or_else(|| format!("{} was built by an incompatible version, rebuild it", path.display()))
+    }
+
+    /// Like `open`, but an index built by an incompatible version is `None`
+    fn read(path: &Path) -> Result<Option<SchematicIndex>, String> {
+        if !path.exists() {
+            return Ok(Some(SchematicIndex::empty(path)));
+        }
+
+        let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic).map_err(|e| e.to_string())?;
+        if magic[..6] != MAGIC[..6] {
+            return Err(format!("{} is not a schemsearch index", path.display()));
+        }
+        if &magic != MAGIC {
+            return Ok(None);
+        }
+
+        let mut positions = [0u8; 16];
+        file.seek(SeekFrom::End(-16)).map_err(|e| e.to_string())?;
+        file.read_exact(&mut positions).map_err(|e| e.to_string())?;
+        let postings_position = u64::from_le_bytes(positions[..8].try_into().unwrap());
+        let entries_position = u64::from_le_bytes(positions[8..].try_into().unwrap());
+        file.seek(SeekFrom::Start(entries_position)).map_err(|e| e.to_string())?;
+        let entries: Vec<IndexEntry> = bincode::deserialize_from(&mut file).map_err(|e| e.to_string())?;
+
+        Ok(Some(SchematicIndex { path: path.to_path_buf(), postings_position, entries }))
+    }
+
+    fn empty(path: &Path) -> SchematicIndex {
+        SchematicIndex { path: path.to_path_buf(), postings_position: 0, entries: Vec::new() }
+    }
+
+    /// Updates the index at `path`, an index built by an incompatible version is rebuilt from scratch
+    pub fn build(path: &Path, files: &[PathBuf]) -> Result<IndexStats, String> {
+        let old = SchematicIndex::read(path)?.unwrap_or_else(|| SchematicIndex::empty(path));
+        let old_neighbourhoods = old.neighbourhoods()?;
+        let previous: HashMap<&str, (&IndexEntry, Option<&Vec<u32>>)> = old.entries.iter().enumerate()
+            .map(|(i, e)| (e.path.as_str(), (e, old_neighbourhoods.get(i))))
+            .collect();
+
+        let temp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path).map_err(|e| e.to_string())?);
+        writer.write_all(MAGIC).map_err(|e| e.to_string())?;
+        let mut position = MAGIC.len() as u64;
+
+        let mut stats = IndexStats::default();
+        let mut entries = Vec::with_capacity(files.len());
+        let mut postings: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        let mut seen = HashSet::new();
+
+        for chunk in files.chunks(CHUNK_SIZE) {
+            let indexed: Vec<(String, Result<IndexedFile, String>)> = chunk.par_iter().map(|file| {
+                let key = index_key(file);
+                let result = index_file(file, &key, previous.get(key.as_str()).copied(), &old);
+                (key, result)
+            }).collect();
+
+            for (key, result) in indexed {
+                if !seen.insert(key.clone()) {
+                    continue;
+                }
+                let (mut entry, data, hashes) = match result {
+                    Ok(IndexedFile::Unchanged(entry, data, hashes)) => { stats.unchanged += 1; (entry, data, hashes) }
+                    Ok(IndexedFile::Added(entry, data, hashes)) => { stats.added += 1; (entry, data, hashes) }
+                    Ok(IndexedFile::Updated(entry, data, hashes)) => { stats.updated += 1; (entry, data, hashes) }
+                    Err(e) => {
+                        stats.errors.push((key, e));
+                        continue;
+                    }
+                };
+                writer.write_all(&data).map_err(|e| e.to_string())?;
+                entry.data_position = position;
+                entry.data_length = data.len() as u64;
+                position += data.len() as u64;
+                for hash in hashes {
+                    postings.entry(hash).or_default().push(entries.len() as u32);
+                }
+                entries.push(entry);
+            }
+        }
+
+        stats.removed = old.entries.iter().filter(|e| !seen.contains(&e.path)).count();
+
+        let postings: Vec<(u32, Vec<u32>)> = postings.into_iter().collect();
+        let postings_data = bincode::serialize(&postings).map_err(|e| e.to_string())?;
+        writer.write_all(&postings_data).map_err(|e| e.to_string())?;
+        let entries_position = position + postings_data.len() as u64;
+
+        bincode::serialize_into(&mut writer, &entries).map_err(|e| e.to_string())?;
+        writer.write_all(&position.to_le_bytes()).map_err(|e| e.to_string())?;
+        writer.write_all(&entries_position.to_le_bytes()).map_err(|e| e.to_string())?;
+        writer.flush().map_err(|e| e.to_string())?;
+        drop(writer);
+
+        std::fs::rename(&temp_path, path).map_err(|e| e.to_string())?;
+        Ok(stats)
+    }
+
+    pub fn load_schematic(&self, entry: &IndexEntry) -> Result<SpongeSchematic, String> {
+        let blocks: IndexedBlocks = bincode::deserialize(&self.read_data(entry)?).map_err(|e| e.to_string())?;
+
+        let mut metadata = CompoundTag::new();
+        metadata.insert_i32("WEOffsetX", entry.origin[0]);
+        metadata.insert_i32("WEOffsetY", entry.origin[1]);
+        metadata.insert_i32("WEOffsetZ", entry.origin[2]);
+
+        Ok(SpongeSchematic {
+            data_version: entry.data_version,
+            metadata,
+            width: entry.width,
+            height: entry.height,
+            length: entry.length,
+            offset: entry.offset,
+            palette_max: blocks.palette.len() as i32,
+            palette: blocks.palette.into_iter().enumerate().map(|(id, name)| (name, id as i32)).collect(),
+            block_data: read_varint_array(&blocks.block_data),
+            block_entities: blocks.block_entities.into_iter().map(|(id, pos)| BlockEntity { id, pos }).collect(),
+            entities: None,
+        })
+    }
+
+    pub fn candidates(&self, pattern: &SpongeSchematic, search_behavior: &SearchBehavior) -> Result<Vec<usize>, String> {
+        let fitting = (0..self.entries.len()).filter(|i| self.entries[*i].fits(pattern));
+        if search_behavior.ignore_air {
+            return Ok(fitting.collect());
+        }
+
+        let hashes = neighbourhoods(pattern, search_behavior.air_as_any);
+        let skip_amount = ((pattern.block_data.len() as f32 * (1.0 - search_behavior.threshold)) as f64).ceil() as usize;
+        let allowed = skip_amount.saturating_sub(1) * MAX_BROKEN_PER_BLOCK;
+        if hashes.len() <= allowed {
+            return Ok(fitting.collect());
+        }
+        let required = hashes.len() - allowed;
+
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for hash in hashes {
+            *counts.entry(hash).or_insert(0) += 1;
+        }
+
+        let postings = self.postings()?;
+        let mut scores = vec![0; self.entries.len()];
+        for (hash, count) in counts {
+            if let Ok(i) = postings.binary_search_by_key(&hash, |(h, _)| *h) {
+                for entry in postings[i].1.iter() {
+                    scores[*entry as usize] += count;
+                }
+            }
+        }
+
+        Ok(fitting.filter(|i| scores[*i] >= required).collect())
+    }
+
+    fn postings(&self) -> Result<Vec<(u32, Vec<u32>)>, String> {
+        if self.entries.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut file = BufReader::new(File::open(&self.path).map_err(|e| e.to_string())?);
+        file.seek(SeekFrom::Start(self.postings_position)).map_err(|e| e.to_string())?;
+        bincode::deserialize_from(&mut file).map_err(|e| e.to_string())
+    }
+
+    fn neighbourhoods(&self) -> Result<Vec<Vec<u32>>, String> {
+        let mut neighbourhoods = vec![Vec::new(); self.entries.len()];
+        for (hash, entries) in self.postings()? {
+            for entry in entries {
+                neighbourhoods[entry as usize].push(hash);
+            }
+        }
+        Ok(neighbourhoods)
+    }
+
+    fn read_data(&self, entry: &IndexEntry) -> Result<Vec<u8>, String> {
+        let mut file = File::open(&self.path).map_err(|e| e.to_string())?;
+        file.seek(SeekFrom::Start(entry.data_position)).map_err(|e| e.to_string())?;
+        let mut data = vec![0u8; entry.data_length as usize];
+        file.read_exact(&mut data).map_err(|e| e.to_string())?;
+        Ok(data)
+    }
+}
+
+fn index_file(path: &Path, key: &str, previous: Option<(&IndexEntry, Option<&Vec<u32>>)>, old: &SchematicIndex) -> Result<IndexedFile, String> {
+    let metadata = std::fs::metadata(path).map_err(|e| e.to_string())?;
+    let modified = metadata.modified()
+        .map_err(|e| e.to_string())?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_nanos();
+    let size = metadata.len();
+
+    if let Some((previous, Some(hashes))) = previous {
+        if previous.modified == modified && previous.size == size {
+            return Ok(IndexedFile::Unchanged(previous.clone(), old.read_data(previous)?, hashes.clone()));
+        }
+    }
+
+    let content = std::fs::read(path).map_err(|e| e.to_string())?;
+    let hash = hash_content(&content);
+
+    if let Some((previous, Some(hashes))) = previous {
+        if previous.hash == hash {
+            let mut entry = previous.clone();
+            entry.modified = modified;
+            entry.size = size;
+            return Ok(IndexedFile::Unchanged(entry, old.read_data(previous)?, hashes.clone()));
+        }
+    }
+
+    let schem = SpongeSchematic::load_data(&mut content.as_slice())?;
+    let (blocks, fingerprint) = normalize(&schem);
+    let data = bincode::serialize(&blocks).map_err(|e| e.to_string())?;
+    let mut hashes = neighbourhoods(&schem, false);
+    hashes.sort_unstable();
+    hashes.dedup();
+
+    let entry = IndexEntry {
+        path: key.to_string(),
+        name: path.file_stem().map(|x| x.to_string_lossy().to_string()).unwrap_or_else(|| key.to_string()),
+        modified,
+        size,
+        hash,
+        data_version: schem.data_version,
+        width: schem.width,
+        height: schem.height,
+        length: schem.length,
+        offset: schem.offset,
+        origin: schem.origin(),
+        fingerprint,
+        data_position: 0,
+        data_length: 0,
+    };
+
+    Ok(match previous {
+        None => IndexedFile::Added(entry, data, hashes),
+        Some(_) => IndexedFile::Updated(entry, data, hashes),
+    })
+}
+
+fn normalize(schem: &SpongeSchematic) -> (IndexedBlocks, Fingerprint) {
+    let mut palette: Vec<(&String, i32)> = schem.palette.iter().map(|(k, v)| (k, *v)).collect();
+    palette.sort();
+
+    let max_id = palette.iter().map(|(_, id)| *id).max().unwrap_or(0) as usize;
+    let mut mapping = vec![0; max_id + 1];
+    for (new_id, (_, old_id)) in palette.iter().enumerate() {
+        mapping[*old_id as usize] = new_id as i32;
+    }
+    let block_data: Vec<i32> = schem.block_data.iter().map(|b| mapping[*b as usize]).collect();
+
+    let blocks = IndexedBlocks {
+        palette: palette.into_iter().map(|(name, _)| name.clone()).collect(),
+        block_data: write_varint_array(&block_data),
+        block_entities: schem.block_entities.iter().map(|e| (e.id.clone(), e.pos)).collect(),
+    };
+    (blocks, fingerprint(schem, false))
+}
+
+fn index_key(path: &Path) -> String {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().to_string()
+}
+
+fn hash_content(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use schemsearch_lib::search::search;
+    use schemsearch_lib::SearchBehavior;
+    use super::*;
+
+    fn index_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("schemsearch-index-{}-{}.idx", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_build_index() {
+        let path = index_path("build");
+        let files = vec![PathBuf::from("../tests/Random.schem"), PathBuf::from("../tests/simple.schem")];
+
+        let stats = SchematicIndex::build(&path, &files).unwrap();
+        assert_eq!(stats.added, 2);
+
+        let stats = SchematicIndex::build(&path, &files[..1]).unwrap();
+        assert_eq!(stats.unchanged, 1);
+        assert_eq!(stats.removed, 1);
+
+        let index = SchematicIndex::open(&path).unwrap();
+        assert_eq!(index.entries.len(), 1);
+        assert_eq!(index.entries[0].name, "Random");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_build_over_other_files() {
+        let path = index_path("other");
+        let files = vec![PathBuf::from("../tests/simple.schem")];
+
+        std::fs::write(&path, b"not an index").unwrap();
+        assert!(SchematicIndex::build(&path, &files).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"not an index");
+
+        std::fs::write(&path, b"SCHIDX01 truncated").unwrap();
+        assert_eq!(SchematicIndex::build(&path, &files).unwrap().added, 1);
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 4]).unwrap();
+        assert!(SchematicIndex::build(&path, &files).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_search_index() {
+        let path = index_path("search");
+        let file = PathBuf::from("../tests/Random.schem");
+        SchematicIndex::build(&path, std::slice::from_ref(&file)).unwrap();
+
+        let index = SchematicIndex::open(&path).unwrap();
+        let schematic = SpongeSchematic::load(&file).unwrap();
+        let indexed = index.load_schematic(&index.entries[0]).unwrap();
+        assert_eq!(schematic.block_data.len(), indexed.block_data.len());
+        assert_eq!(schematic.origin(), indexed.origin());
+
+        let pattern = SpongeSchematic::load(&PathBuf::from("../tests/Pattern.schem")).unwrap();
+        let behavior = SearchBehavior {
+            ignore_block_data: true,
+            ignore_block_entities: true,
+            ignore_entities: true,
+            ignore_air: false,
+            air_as_any: false,
+            threshold: 0.9,
+            invalid_nbt: false
+        };
+        let expected = search(schematic, &pattern, behavior);
+        let matches = search(indexed, &pattern, behavior);
+        assert_eq!(matches.len(), expected.len());
+        assert_eq!(matches[0].x, expected[0].x);
+        assert_eq!(matches[0].world_z, expected[0].world_z);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_candidates() {
+        let path = index_path("candidates");
+        let files = vec![
+            PathBuf::from("../tests/simple.schem"),
+            PathBuf::from("../tests/warships/GreyFly-by-Bosslar.schem"),
+            PathBuf::from("../tests/endstone_large.schem"),
+        ];
+        SchematicIndex::build(&path, &files).unwrap();
+
+        let index = SchematicIndex::open(&path).unwrap();
+        let pattern = SpongeSchematic::load(&PathBuf::from("../tests/gray_castle_complex.schem")).unwrap();
+        let candidates = index.candidates(&pattern, &SearchBehavior {
+            ignore_block_data: false,
+            ignore_block_entities: false,
+            ignore_entities: false,
+            ignore_air: false,
+            air_as_any: false,
+            threshold: 0.99,
+            invalid_nbt: false
+        }).unwrap();
+
+        assert!(candidates.iter().any(|i| index.entries[*i].name == "GreyFly-by-Bosslar"));
+        assert!(!candidates.iter().any(|i| index.entries[*i].name == "simple"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_candidates_across_versions() {
+        let blocks = |data_version: i32, path: &str| SpongeSchematic {
+            data_version,
+            metadata: CompoundTag::new(),
+            width: 6,
+            height: 6,
+            length: 6,
+            offset: [0, 0, 0],
+            palette_max: 2,
+            palette: vec![("minecraft:stone".to_owned(), 0), (path.to_owned(), 1)].into_iter().collect(),
+            block_data: (0..216).map(|i| ((i * 7 + i / 5) % 3 == 0) as i32).collect(),
+            block_entities: vec![],
+            entities: None,
+        };
+        let file = std::env::temp_dir().join(format!("schemsearch-index-old-{}.schem", std::process::id()));
+        blocks(2230, "minecraft:grass_path").save(&file, 2).unwrap();
+        let path = index_path("versions");
+        SchematicIndex::build(&path, std::slice::from_ref(&file)).unwrap();
+
+        let index = SchematicIndex::open(&path).unwrap();
+        let pattern = blocks(3700, "minecraft:dirt_path");
+        assert!(!neighbourhoods(&pattern, false).is_empty());
+        let candidates = index.candidates(&pattern, &SearchBehavior {
+            ignore_block_data: false,
+            ignore_block_entities: false,
+            ignore_entities: false,
+            ignore_air: false,
+            air_as_any: false,
+            threshold: 1.0,
+            invalid_nbt: false
+        }).unwrap();
+
+        assert_eq!(candidates, vec![0]);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&file).unwrap();
+    }
+}
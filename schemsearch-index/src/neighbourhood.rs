@@ -0,0 +1,82 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use schemsearch_files::SpongeSchematic;
+use schemsearch_files::upgrade::upgrade_block;
+use schemsearch_lib::normalize_data;
+
+pub(crate) const NEIGHBOURHOOD_SIZE: usize = 2;
+pub(crate) const MAX_BROKEN_PER_BLOCK: usize = NEIGHBOURHOOD_SIZE * NEIGHBOURHOOD_SIZE * NEIGHBOURHOOD_SIZE;
+const SAMPLE_RATE: u32 = 4;
+/// The block names are upgraded through every known fix before hashing, like `search` does for the older side.
+/// Adding a fix changes the hashes, so the index format has to be bumped with it.
+const HASH_DATA_VERSION: i32 = i32::MAX;
+
+// Hashes every 2x2x2 neighbourhood of upgraded block names without block data. Only hashes divisible by the
+// sample rate are kept, which shrinks the index but keeps the lookup exact for the kept hashes.
+pub(crate) fn neighbourhoods(schem: &SpongeSchematic, skip_air: bool) -> Vec<u32> {
+    let width = schem.width as usize;
+    let height = schem.height as usize;
+    let length = schem.length as usize;
+    if width < NEIGHBOURHOOD_SIZE || height < NEIGHBOURHOOD_SIZE || length < NEIGHBOURHOOD_SIZE {
+        return Vec::new();
+    }
+
+    let max_id = schem.palette.values().copied().max().unwrap_or(0) as usize;
+    let mut hashes = vec![0u64; max_id + 1];
+    let mut air = vec![false; max_id + 1];
+    for (name, id) in schem.palette.iter() {
+        let name = upgrade_block(name, schem.data_version, HASH_DATA_VERSION);
+        let name = normalize_data(&name, true);
+        hashes[*id as usize] = fnv1a(name.as_bytes());
+        air[*id as usize] = matches!(name, "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air");
+    }
+
+    let mut output = Vec::new();
+    for y in 0..=height - NEIGHBOURHOOD_SIZE {
+        for z in 0..=length - NEIGHBOURHOOD_SIZE {
+            for x in 0..=width - NEIGHBOURHOOD_SIZE {
+                let mut hash: u64 = 0xcbf29ce484222325;
+                let mut all_air = true;
+                let mut any_air = false;
+                for j in 0..NEIGHBOURHOOD_SIZE {
+                    for k in 0..NEIGHBOURHOOD_SIZE {
+                        for i in 0..NEIGHBOURHOOD_SIZE {
+                            let block = schem.block_data[(x + i) + width * ((z + k) + (y + j) * length)] as usize;
+                            all_air &= air[block];
+                            any_air |= air[block];
+                            hash = (hash ^ hashes[block]).wrapping_mul(0x100000001b3);
+                        }
+                    }
+                }
+                if all_air || (skip_air && any_air) {
+                    continue;
+                }
+                let hash = (hash ^ (hash >> 32)) as u32;
+                if hash.is_multiple_of(SAMPLE_RATE) {
+                    output.push(hash);
+                }
+            }
+        }
+    }
+    output
+}
+
+#[inline]
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
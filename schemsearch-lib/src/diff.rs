@@ -0,0 +1,277 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use schemsearch_files::{BlockEntity, SpongeSchematic};
+use crate::pattern_mapper::create_reverse_palette;
+
+const AIR_BLOCKS: [&str; 3] = ["minecraft:air", "minecraft:cave_air", "minecraft:void_air"];
+const UNCHANGED_BLOCK: &str = "minecraft:structure_void";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DiffKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlockDiff {
+    pub kind: DiffKind,
+    pub pos: [i32; 3],
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SchematicDiff {
+    pub min: [i32; 3],
+    pub max: [i32; 3],
+    pub data_version: i32,
+    pub blocks: Vec<BlockDiff>,
+    pub block_entities: Vec<BlockDiff>,
+}
+
+impl SchematicDiff {
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.block_entities.is_empty()
+    }
+
+    pub fn count_blocks(&self, kind: DiffKind) -> usize {
+        self.blocks.iter().filter(|d| d.kind == kind).count()
+    }
+
+    pub fn count_block_entities(&self, kind: DiffKind) -> usize {
+        self.block_entities.iter().filter(|d| d.kind == kind).count()
+    }
+
+    pub fn to_schematic(&self, old: &SpongeSchematic) -> SpongeSchematic {
+        let width = (self.max[0] - self.min[0]) as usize;
+        let height = (self.max[1] - self.min[1]) as usize;
+        let length = (self.max[2] - self.min[2]) as usize;
+
+        let mut palette: HashMap<String, i32> = HashMap::new();
+        palette.insert(UNCHANGED_BLOCK.to_string(), 0);
+        let mut block_data = vec![0; width * height * length];
+
+        for diff in &self.blocks {
+            let block = diff.new.clone().unwrap_or_else(|| AIR_BLOCKS[0].to_string());
+            let next_id = palette.len() as i32;
+            let id = *palette.entry(block).or_insert(next_id);
+            let [x, y, z] = self.relative(diff.pos);
+            block_data[x as usize + width * (z as usize + y as usize * length)] = id;
+        }
+
+        let block_entities = self.block_entities.iter()
+            .filter_map(|diff| diff.new.as_ref().map(|id| BlockEntity {
+                id: id.clone(),
+                pos: self.relative(diff.pos),
+            }))
+            .collect();
+
+        let mut metadata = old.metadata.clone();
+        if let (Ok(x), Ok(y), Ok(z)) = (metadata.get_i32("WEOffsetX"), metadata.get_i32("WEOffsetY"), metadata.get_i32("WEOffsetZ")) {
+            metadata.insert_i32("WEOffsetX", x + self.min[0]);
+            metadata.insert_i32("WEOffsetY", y + self.min[1]);
+            metadata.insert_i32("WEOffsetZ", z + self.min[2]);
+        }
+
+        SpongeSchematic {
+            data_version: self.data_version,
+            metadata,
+            width: width as u16,
+            height: height as u16,
+            length: length as u16,
+            offset: [old.offset[0] + self.min[0], old.offset[1] + self.min[1], old.offset[2] + self.min[2]],
+            palette_max: palette.len() as i32,
+            palette,
+            block_data,
+            block_entities,
+            entities: None,
+        }
+    }
+
+    #[inline]
+    fn relative(&self, pos: [i32; 3]) -> [i32; 3] {
+        [pos[0] - self.min[0], pos[1] - self.min[1], pos[2] - self.min[2]]
+    }
+}
+
+pub fn diff(old: &SpongeSchematic, new: &SpongeSchematic, offset: [i32; 3]) -> SchematicDiff {
+    let min = [0.min(offset[0]), 0.min(offset[1]), 0.min(offset[2])];
+    let max = [
+        (old.width as i32).max(offset[0] + new.width as i32),
+        (old.height as i32).max(offset[1] + new.height as i32),
+        (old.length as i32).max(offset[2] + new.length as i32),
+    ];
+
+    let old_palette = create_reverse_palette(old);
+    let new_palette = create_reverse_palette(new);
+
+    let mut blocks = Vec::new();
+    for y in min[1]..max[1] {
+        for z in min[2]..max[2] {
+            for x in min[0]..max[0] {
+                let old_block = block_at(old, &old_palette, [x, y, z]);
+                let new_block = block_at(new, &new_palette, [x - offset[0], y - offset[1], z - offset[2]]);
+                if let Some(kind) = diff_kind(old_block, new_block) {
+                    blocks.push(BlockDiff {
+                        kind,
+                        pos: [x, y, z],
+                        old: old_block.map(|b| b.to_string()),
+                        new: new_block.map(|b| b.to_string()),
+                    });
+                }
+            }
+        }
+    }
+
+    let old_entities: HashMap<[i32; 3], &str> = old.block_entities.iter()
+        .map(|e| (e.pos, e.id.as_str()))
+        .collect();
+    let new_entities: HashMap<[i32; 3], &str> = new.block_entities.iter()
+        .map(|e| ([e.pos[0] + offset[0], e.pos[1] + offset[1], e.pos[2] + offset[2]], e.id.as_str()))
+        .collect();
+
+    let mut positions: Vec<[i32; 3]> = old_entities.keys().chain(new_entities.keys()).copied().collect();
+    positions.sort_by_key(|p| (p[1], p[2], p[0]));
+    positions.dedup();
+
+    let block_entities = positions.into_iter().filter_map(|pos| {
+        let old_entity = old_entities.get(&pos).copied();
+        let new_entity = new_entities.get(&pos).copied();
+        diff_kind(old_entity, new_entity).map(|kind| BlockDiff {
+            kind,
+            pos,
+            old: old_entity.map(|e| e.to_string()),
+            new: new_entity.map(|e| e.to_string()),
+        })
+    }).collect();
+
+    SchematicDiff {
+        min,
+        max,
+        data_version: new.data_version,
+        blocks,
+        block_entities,
+    }
+}
+
+#[inline]
+fn diff_kind(old: Option<&str>, new: Option<&str>) -> Option<DiffKind> {
+    match (old, new) {
+        (None, None) => None,
+        (None, Some(_)) => Some(DiffKind::Added),
+        (Some(_), None) => Some(DiffKind::Removed),
+        (Some(a), Some(b)) if a == b => None,
+        (Some(_), Some(_)) => Some(DiffKind::Changed),
+    }
+}
+
+#[inline]
+fn block_at<'a>(schem: &SpongeSchematic, reverse_palette: &[&'a str], pos: [i32; 3]) -> Option<&'a str> {
+    let [x, y, z] = pos;
+    if x < 0 || y < 0 || z < 0 || x >= schem.width as i32 || y >= schem.height as i32 || z >= schem.length as i32 {
+        return None;
+    }
+    let index = x as usize + schem.width as usize * (z as usize + y as usize * schem.length as usize);
+    let block = reverse_palette[schem.block_data[index] as usize];
+    if AIR_BLOCKS.contains(&block) {
+        None
+    } else {
+        Some(block)
+    }
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use nbt::CompoundTag;
+    use super::*;
+
+    fn schematic(width: u16, blocks: &[&str], block_entities: Vec<BlockEntity>) -> SpongeSchematic {
+        let mut palette: HashMap<String, i32> = HashMap::new();
+        let block_data = blocks.iter().map(|b| {
+            let next_id = palette.len() as i32;
+            *palette.entry(b.to_string()).or_insert(next_id)
+        }).collect();
+        SpongeSchematic {
+            data_version: 1,
+            metadata: CompoundTag::new(),
+            width,
+            height: 1,
+            length: 1,
+            offset: [0, 0, 0],
+            palette_max: palette.len() as i32,
+            palette,
+            block_data,
+            block_entities,
+            entities: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let schem = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
+
+        assert!(diff(&schem, &schem, [0, 0, 0]).is_empty());
+    }
+
+    #[test]
+    fn test_diff_blocks() {
+        let old = schematic(3, &["minecraft:stone", "minecraft:dirt", "minecraft:air"], vec![]);
+        let new = schematic(3, &["minecraft:stone", "minecraft:air", "minecraft:chest"], vec![
+            BlockEntity { id: "minecraft:chest".to_owned(), pos: [2, 0, 0] }
+        ]);
+
+        let diff = diff(&old, &new, [0, 0, 0]);
+
+        assert_eq!(diff.blocks, vec![
+            BlockDiff { kind: DiffKind::Removed, pos: [1, 0, 0], old: Some("minecraft:dirt".to_owned()), new: None },
+            BlockDiff { kind: DiffKind::Added, pos: [2, 0, 0], old: None, new: Some("minecraft:chest".to_owned()) },
+        ]);
+        assert_eq!(diff.count_block_entities(DiffKind::Added), 1);
+    }
+
+    #[test]
+    fn test_diff_offset() {
+        let old = schematic(2, &["minecraft:stone", "minecraft:dirt"], vec![]);
+        let new = schematic(2, &["minecraft:dirt", "minecraft:glass"], vec![]);
+
+        let diff = diff(&old, &new, [1, 0, 0]);
+
+        assert_eq!(diff.min, [0, 0, 0]);
+        assert_eq!(diff.max, [3, 1, 1]);
+        assert_eq!(diff.count_blocks(DiffKind::Removed), 1);
+        assert_eq!(diff.count_blocks(DiffKind::Added), 1);
+        assert_eq!(diff.count_blocks(DiffKind::Changed), 0);
+    }
+
+    #[test]
+    fn test_diff_to_schematic() {
+        let old = schematic(2, &["minecraft:stone", "minecraft:dirt"], vec![]);
+        let new = schematic(2, &["minecraft:stone", "minecraft:glass"], vec![]);
+
+        let schem = diff(&old, &new, [0, 0, 0]).to_schematic(&old);
+
+        assert_eq!(schem.block_data.len(), 2);
+        assert_eq!(schem.block_data[0], schem.palette[UNCHANGED_BLOCK]);
+        assert_eq!(schem.block_data[1], schem.palette["minecraft:glass"]);
+    }
+}
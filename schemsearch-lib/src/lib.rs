@@ -18,6 +18,10 @@
 pub mod pattern_mapper;
 pub mod search;
 pub mod nbt_search;
+pub mod diff;
+pub mod similarity;
+pub mod stats;
+pub mod validation;
 
 use serde::{Serialize, Deserialize};
 
@@ -32,14 +36,26 @@ pub struct SearchBehavior {
     pub invalid_nbt: bool,
 }
 
+/// `x`, `y` and `z` are relative to the minimum corner of the schematic, `world_x`, `world_y`, `world_z` and the bounding box
+/// are relative to the point WorldEdit pastes the schematic at, see `SpongeSchematic::origin`
 #[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
 pub struct Match {
     pub x: u16,
     pub y: u16,
     pub z: u16,
+    pub world_x: i32,
+    pub world_y: i32,
+    pub world_z: i32,
+    pub bounding_box: BoundingBox,
     pub percent: f32,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BoundingBox {
+    pub min: [i32; 3],
+    pub max: [i32; 3],
+}
+
 #[inline]
 pub fn normalize_data(data: &str, ignore_data: bool) -> &str {
     if ignore_data {
@@ -49,6 +65,11 @@ pub fn normalize_data(data: &str, ignore_data: bool) -> &str {
     }
 }
 
+#[inline]
+pub fn is_air(name: &str) -> bool {
+    matches!(name, "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air")
+}
+
 #[allow(unused_imports)]
 #[cfg(test)]
 mod tests {
@@ -74,6 +95,43 @@ mod tests {
         assert_eq!(schematic.palette_max, schematic.palette.len() as i32);
     }
 
+    #[test]
+    fn test_save_schematic() {
+        let schematic = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
+        let mut data = Vec::new();
+        schematic.save_data(&mut data, 2).unwrap();
+        let loaded = SpongeSchematic::load_data(&mut data.as_slice()).unwrap();
+
+        assert_eq!(schematic.palette, loaded.palette);
+        assert_eq!(schematic.block_data, loaded.block_data);
+        assert_eq!(schematic.block_entities.len(), loaded.block_entities.len());
+    }
+
+    #[test]
+    fn test_load_schematic_v3() {
+        let schematic = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
+        let v3 = SpongeSchematic::load(&PathBuf::from("../tests/simple_v3.schem")).unwrap();
+
+        assert_eq!((v3.width, v3.height, v3.length), (schematic.width, schematic.height, schematic.length));
+        assert_eq!(v3.palette, schematic.palette);
+        assert_eq!(v3.palette_max, schematic.palette_max);
+        assert_eq!(v3.block_data, schematic.block_data);
+        assert_eq!(v3.origin(), schematic.origin());
+    }
+
+    #[test]
+    fn test_save_schematic_v3() {
+        let schematic = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
+        let mut data = Vec::new();
+        schematic.save_data(&mut data, 3).unwrap();
+        let loaded = SpongeSchematic::load_data(&mut data.as_slice()).unwrap();
+
+        assert_eq!(schematic.palette, loaded.palette);
+        assert_eq!(schematic.block_data, loaded.block_data);
+        assert_eq!(schematic.origin(), loaded.origin());
+        assert_eq!(schematic.palette_max, loaded.palette_max);
+    }
+
     #[test]
     fn test_strip_schem() {
         let schematic = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
@@ -136,6 +194,115 @@ mod tests {
         assert_eq!(matches[0].percent, 1.0);
     }
 
+    #[test]
+    pub fn test_search_world_coordinates() {
+        let schematic = SpongeSchematic::load(&PathBuf::from("../tests/Random.schem")).unwrap();
+        let pattern = SpongeSchematic::load(&PathBuf::from("../tests/Pattern.schem")).unwrap();
+        let origin = schematic.origin();
+
+        let matches = search(schematic, &pattern, SearchBehavior {
+            ignore_block_data: true,
+            ignore_block_entities: true,
+            ignore_entities: true,
+            ignore_air: false,
+            air_as_any: false,
+            threshold: 0.9,
+            invalid_nbt: false
+        });
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].world_x, origin[0] + 1);
+        assert_eq!(matches[0].world_y, origin[1]);
+        assert_eq!(matches[0].world_z, origin[2] + 3);
+        assert_eq!(matches[0].bounding_box.min, [matches[0].world_x, matches[0].world_y, matches[0].world_z]);
+        assert_eq!(matches[0].bounding_box.max, [
+            matches[0].world_x + pattern.width as i32 - 1,
+            matches[0].world_y + pattern.height as i32 - 1,
+            matches[0].world_z + pattern.length as i32 - 1,
+        ]);
+    }
+
+    #[test]
+    pub fn test_search_without_worldedit_offset() {
+        let schematic = SpongeSchematic {
+            data_version: 3700,
+            metadata: nbt::CompoundTag::new(),
+            width: 3,
+            height: 1,
+            length: 1,
+            offset: [100, 64, -20],
+            palette_max: 2,
+            palette: vec![("minecraft:stone".to_owned(), 0), ("minecraft:dirt".to_owned(), 1)].into_iter().collect(),
+            block_data: vec![0, 1, 0],
+            block_entities: vec![],
+            entities: None,
+        };
+        let pattern = SpongeSchematic {
+            width: 1,
+            offset: [0, 0, 0],
+            palette_max: 1,
+            palette: vec![("minecraft:dirt".to_owned(), 0)].into_iter().collect(),
+            block_data: vec![0],
+            ..schematic.clone()
+        };
+
+        let matches = search(schematic, &pattern, SearchBehavior {
+            ignore_block_data: false,
+            ignore_block_entities: true,
+            ignore_entities: true,
+            ignore_air: false,
+            air_as_any: false,
+            threshold: 0.9,
+            invalid_nbt: false
+        });
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!([matches[0].world_x, matches[0].world_y, matches[0].world_z], [1, 0, 0]);
+    }
+
+    #[test]
+    pub fn test_search_across_versions() {
+        let schematic = SpongeSchematic {
+            data_version: 2230,
+            metadata: nbt::CompoundTag::new(),
+            width: 3,
+            height: 1,
+            length: 1,
+            offset: [0, 0, 0],
+            palette_max: 2,
+            palette: vec![("minecraft:stone".to_owned(), 0), ("minecraft:grass_path".to_owned(), 1)].into_iter().collect(),
+            block_data: vec![0, 1, 0],
+            block_entities: vec![],
+            entities: None,
+        };
+        let pattern = SpongeSchematic {
+            data_version: 3700,
+            metadata: nbt::CompoundTag::new(),
+            width: 1,
+            height: 1,
+            length: 1,
+            offset: [0, 0, 0],
+            palette_max: 1,
+            palette: vec![("minecraft:dirt_path".to_owned(), 0)].into_iter().collect(),
+            block_data: vec![0],
+            block_entities: vec![],
+            entities: None,
+        };
+
+        let matches = search(schematic, &pattern, SearchBehavior {
+            ignore_block_data: false,
+            ignore_block_entities: true,
+            ignore_entities: true,
+            ignore_air: false,
+            air_as_any: false,
+            threshold: 0.9,
+            invalid_nbt: false
+        });
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].x, 1);
+    }
+
     #[test]
     pub fn test_search_ws() {
         let schematic = SpongeSchematic::load(&PathBuf::from("../tests/warships/GreyFly-by-Bosslar.schem")).unwrap();
@@ -1,37 +1,184 @@
-use std::borrow::ToOwned;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::iter::Iterator;
 use lazy_static::lazy_static;
-use schemsearch_files::SpongeSchematic;
+use serde::{Deserialize, Serialize};
+use schemsearch_files::{BlockEntity, SpongeSchematic};
+use crate::normalize_data;
 
-const NBT_BLOCKS: &str = include_str!("blocks.txt");
+/// Changes to the blocks with block entities, keyed by the first data version they apply to.
+/// Lines starting with `-` remove a block from the previous table.
+const NBT_BLOCK_CHANGES: [(i32, &str); 10] = [
+    (1519, include_str!("blocks/1.13.txt")),
+    (1952, include_str!("blocks/1.14.txt")),
+    (2225, include_str!("blocks/1.15.txt")),
+    (2566, include_str!("blocks/1.16.txt")),
+    (2724, include_str!("blocks/1.17.txt")),
+    (3105, include_str!("blocks/1.19.txt")),
+    (3218, include_str!("blocks/1.19.3.txt")),
+    (3463, include_str!("blocks/1.20.txt")),
+    (3953, include_str!("blocks/1.21.txt")),
+    (4189, include_str!("blocks/1.21.4.txt")),
+];
 
 lazy_static! {
-    static ref NBT_BLOCKS_SET: HashSet<String> = {
-        NBT_BLOCKS.lines().map(|x| format!("minecraft:{}", x)).collect()
+    static ref NBT_BLOCK_TABLES: Vec<(i32, HashSet<String>)> = {
+        let mut tables: Vec<(i32, HashSet<String>)> = Vec::with_capacity(NBT_BLOCK_CHANGES.len());
+        for (data_version, changes) in NBT_BLOCK_CHANGES {
+            let mut table = tables.last().map(|(_, table)| table.clone()).unwrap_or_default();
+            for line in changes.lines().filter(|x| !x.is_empty()) {
+                match line.strip_prefix('-') {
+                    Some(block) => table.remove(&format!("minecraft:{}", block)),
+                    None => table.insert(format!("minecraft:{}", line)),
+                };
+            }
+            tables.push((data_version, table));
+        }
+        tables
     };
 }
 
+/// The blocks with block entities in the given data version, older versions use the oldest table
+pub fn nbt_blocks(data_version: i32) -> &'static HashSet<String> {
+    let tables = &*NBT_BLOCK_TABLES;
+    let index = tables.iter().rposition(|(start, _)| *start <= data_version).unwrap_or(0);
+    &tables[index].1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum NbtIssueKind {
+    /// A block that needs a block entity has none
+    MissingBlockEntity,
+    /// A block entity sits on a block that doesn't have one
+    OrphanBlockEntity,
+    /// The block entity id doesn't belong to the block at its position
+    IdMismatch,
+    /// More than one block entity at the same position
+    DuplicatePosition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NbtIssue {
+    pub kind: NbtIssueKind,
+    pub pos: [i32; 3],
+    pub block: Option<String>,
+    pub block_entity: Option<String>,
+}
+
+impl Display for NbtIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let [x, y, z] = self.pos;
+        let block = self.block.as_deref().unwrap_or("<none>");
+        let block_entity = self.block_entity.as_deref().unwrap_or("<none>");
+        match self.kind {
+            NbtIssueKind::MissingBlockEntity => write!(f, "{} at {},{},{} has no block entity", block, x, y, z),
+            NbtIssueKind::OrphanBlockEntity => write!(f, "block entity {} at {},{},{} sits on {}", block_entity, x, y, z, block),
+            NbtIssueKind::IdMismatch => write!(f, "block entity {} at {},{},{} doesn't match {}", block_entity, x, y, z, block),
+            NbtIssueKind::DuplicatePosition => write!(f, "block entity {} at {},{},{} is a duplicate", block_entity, x, y, z),
+        }
+    }
+}
+
 pub fn has_invalid_nbt(schem: SpongeSchematic) -> bool {
-    if schem.block_entities.is_empty() && schem.palette.keys().any(|v| NBT_BLOCKS_SET.contains(v)) {
+    let nbt_blocks = nbt_blocks(schem.data_version);
+    if schem.block_entities.is_empty() && schem.palette.keys().any(|v| nbt_blocks.contains(normalize_data(v, true))) {
         return true;
     }
 
-    let nbt_blocks = schem.palette.iter().filter(|(k, _)| NBT_BLOCKS_SET.contains(k.to_owned())).map(|(_, v)| *v).collect::<HashSet<i32>>();
+    !nbt_diagnostics(&schem).is_empty()
+}
 
-    for (i, block_entity) in schem.block_data.iter().enumerate() {
-        if nbt_blocks.contains(&*block_entity) {
-            // i = x + z * Width + y * Width * Length
-            let x = i % schem.width as usize;
-            let z = (i / schem.width as usize) % schem.length as usize;
-            let y = i / (schem.width as usize * schem.length as usize);
-            if schem.block_entities.iter().any(|e| !e.pos.eq(&[x as i32, y as i32, z as i32])) {
-                return true;
-            }
+pub fn nbt_diagnostics(schem: &SpongeSchematic) -> Vec<NbtIssue> {
+    let mut issues = Vec::new();
+    let nbt_blocks = nbt_blocks(schem.data_version);
+    let names: HashMap<i32, &str> = schem.palette.iter().map(|(k, v)| (*v, k.as_str())).collect();
+    let width = schem.width as usize;
+    let length = schem.length as usize;
+    let volume = width * schem.height as usize * length;
+
+    let mut entities: HashMap<[i32; 3], &BlockEntity> = HashMap::with_capacity(schem.block_entities.len());
+    for entity in schem.block_entities.iter() {
+        if entities.insert(entity.pos, entity).is_some() {
+            issues.push(NbtIssue {
+                kind: NbtIssueKind::DuplicatePosition,
+                pos: entity.pos,
+                block: None,
+                block_entity: Some(entity.id.clone()),
+            });
         }
     }
 
-    return false;
+    for (i, block) in schem.block_data.iter().take(volume).enumerate() {
+        let block = normalize_data(names.get(block).copied().unwrap_or(""), true);
+        // i = x + z * Width + y * Width * Length
+        let pos = [(i % width) as i32, (i / (width * length)) as i32, ((i / width) % length) as i32];
+        let entity = entities.remove(&pos);
+        let issue = match entity {
+            None if nbt_blocks.contains(block) => NbtIssueKind::MissingBlockEntity,
+            None => continue,
+            Some(_) if !nbt_blocks.contains(block) => NbtIssueKind::OrphanBlockEntity,
+            Some(entity) if namespaced(&entity.id) != block_entity_id(block) => NbtIssueKind::IdMismatch,
+            Some(_) => continue,
+        };
+        issues.push(NbtIssue {
+            kind: issue,
+            pos,
+            block: Some(block.to_string()),
+            block_entity: entity.map(|e| e.id.clone()),
+        });
+    }
+
+    let mut outside: Vec<&BlockEntity> = entities.into_values().collect();
+    outside.sort_by_key(|e| e.pos);
+    for entity in outside {
+        issues.push(NbtIssue {
+            kind: NbtIssueKind::OrphanBlockEntity,
+            pos: entity.pos,
+            block: None,
+            block_entity: Some(entity.id.clone()),
+        });
+    }
+
+    issues
+}
+
+fn namespaced(id: &str) -> String {
+    if id.contains(':') {
+        id.to_string()
+    } else {
+        format!("minecraft:{}", id)
+    }
+}
+
+/// Maps a block to the id of its block entity, e.g. `minecraft:oak_wall_sign` to `minecraft:sign`
+fn block_entity_id(block: &str) -> String {
+    let name = block.strip_prefix("minecraft:").unwrap_or(block);
+    let id = if name.ends_with("_hanging_sign") {
+        "hanging_sign"
+    } else if name.ends_with("_sign") {
+        "sign"
+    } else if name.ends_with("_banner") {
+        "banner"
+    } else if name.ends_with("_bed") {
+        "bed"
+    } else if name.ends_with("shulker_box") {
+        "shulker_box"
+    } else if name.ends_with("_head") || name.ends_with("_skull") {
+        "skull"
+    } else if name.starts_with("suspicious_") {
+        "brushable_block"
+    } else if name.ends_with("command_block") {
+        "command_block"
+    } else {
+        match name {
+            "moving_piston" => "piston",
+            "bee_nest" => "beehive",
+            "spawner" => "mob_spawner",
+            "soul_campfire" => "campfire",
+            _ => name,
+        }
+    };
+    format!("minecraft:{}", id)
 }
 
 #[allow(unused_imports)]
@@ -107,4 +254,75 @@ mod tests {
 
         assert_eq!(has_invalid_nbt(schem), true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_nbt_diagnostics() {
+        let schem = SpongeSchematic {
+            data_version: 3465,
+            metadata: CompoundTag::new(),
+            width: 4,
+            height: 1,
+            length: 1,
+            offset: [0, 0, 0],
+            palette_max: 3,
+            palette: vec![
+                ("minecraft:chest[facing=north]".to_owned(), 0),
+                ("minecraft:stone".to_owned(), 1),
+                ("minecraft:oak_wall_sign[facing=east]".to_owned(), 2),
+            ].into_iter().collect(),
+            block_data: vec![0, 1, 2, 0],
+            block_entities: vec![
+                BlockEntity { id: "minecraft:furnace".to_owned(), pos: [0, 0, 0] },
+                BlockEntity { id: "minecraft:chest".to_owned(), pos: [1, 0, 0] },
+                BlockEntity { id: "minecraft:sign".to_owned(), pos: [2, 0, 0] },
+                BlockEntity { id: "minecraft:sign".to_owned(), pos: [2, 0, 0] },
+            ],
+            entities: None,
+        };
+
+        let issues: Vec<(NbtIssueKind, [i32; 3])> = nbt_diagnostics(&schem).into_iter().map(|i| (i.kind, i.pos)).collect();
+        assert_eq!(issues, vec![
+            (NbtIssueKind::DuplicatePosition, [2, 0, 0]),
+            (NbtIssueKind::IdMismatch, [0, 0, 0]),
+            (NbtIssueKind::OrphanBlockEntity, [1, 0, 0]),
+            (NbtIssueKind::MissingBlockEntity, [3, 0, 0]),
+        ]);
+    }
+
+    #[test]
+    fn test_nbt_blocks_by_version() {
+        assert!(nbt_blocks(1519).contains("minecraft:sign"));
+        assert!(!nbt_blocks(1519).contains("minecraft:oak_sign"));
+        assert!(nbt_blocks(1976).contains("minecraft:oak_sign"));
+        assert!(!nbt_blocks(1976).contains("minecraft:sign"));
+        assert!(!nbt_blocks(3218).contains("minecraft:oak_hanging_sign"));
+        assert!(nbt_blocks(3465).contains("minecraft:oak_hanging_sign"));
+        assert!(nbt_blocks(3465).contains("minecraft:chest"));
+        assert_eq!(nbt_blocks(0), nbt_blocks(1519));
+    }
+
+    #[test]
+    fn test_has_invalid_nbt_old_version() {
+        let schem = |data_version| SpongeSchematic {
+            data_version,
+            metadata: CompoundTag::new(),
+            width: 1,
+            height: 1,
+            length: 1,
+            offset: [0, 0, 0],
+            palette_max: 1,
+            palette: vec![("minecraft:sign[rotation=0]".to_owned(), 0)].into_iter().collect(),
+            block_data: vec![0],
+            block_entities: vec![
+                BlockEntity {
+                    id: "minecraft:sign".to_owned(),
+                    pos: [0, 0, 0],
+                }
+            ],
+            entities: None,
+        };
+
+        assert!(!has_invalid_nbt(schem(1631)));
+        assert!(has_invalid_nbt(schem(3465)));
+    }
+}
@@ -15,12 +15,13 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use nbt::CompoundTag;
 use schemsearch_files::SpongeSchematic;
 use crate::normalize_data;
 
-fn create_reverse_palette(schem: &SpongeSchematic) -> Vec<&str> {
+pub(crate) fn create_reverse_palette(schem: &SpongeSchematic) -> Vec<&str> {
     let mut reverse_palette = Vec::with_capacity(schem.palette_max as usize);
     (0..schem.palette_max).for_each(|_| reverse_palette.push(""));
     for (key, value) in schem.palette.iter() {
@@ -85,6 +86,9 @@ pub fn match_palette(
     pattern: &SpongeSchematic,
     ignore_data: bool,
 ) -> SpongeSchematic {
+    let data_version = schem.data_version.max(pattern.data_version);
+    let schem = &upgraded(schem, data_version);
+    let pattern = &upgraded(pattern, data_version);
     if ignore_data {
         match_palette_internal(&strip_data(schem), &strip_data(pattern), ignore_data)
     } else {
@@ -92,6 +96,16 @@ pub fn match_palette(
     }
 }
 
+fn upgraded(schem: &SpongeSchematic, data_version: i32) -> Cow<'_, SpongeSchematic> {
+    if schem.data_version < data_version {
+        let mut schem = schem.clone();
+        schem.upgrade(data_version);
+        Cow::Owned(schem)
+    } else {
+        Cow::Borrowed(schem)
+    }
+}
+
 fn match_palette_internal(
     schem: &SpongeSchematic,
     pattern: &SpongeSchematic,
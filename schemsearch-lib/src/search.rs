@@ -1,10 +1,10 @@
 use math::round::ceil;
 use schemsearch_files::SpongeSchematic;
-use crate::{Match, SearchBehavior};
+use crate::{BoundingBox, Match, SearchBehavior};
 use crate::pattern_mapper::{match_palette, match_palette_adapt};
 
 pub fn search(
-    schem: SpongeSchematic,
+    mut schem: SpongeSchematic,
     pattern_schem: &SpongeSchematic,
     search_behavior: SearchBehavior,
 ) -> Vec<Match> {
@@ -16,6 +16,9 @@ pub fn search(
         return Vec::new();
     }
 
+    let origin = schem.origin();
+    schem.upgrade(pattern_schem.data_version);
+
     let pattern_schem = match_palette(&schem, &pattern_schem, search_behavior.ignore_block_data);
 
     let mut matches: Vec<Match> = Vec::with_capacity(4);
@@ -72,10 +75,18 @@ pub fn search(
                 }
 
                 if not_matching < skip_amount {
+                    let world = [origin[0] + x as i32, origin[1] + y as i32, origin[2] + z as i32];
                     matches.push(Match {
                         x: x as u16,
                         y: y as u16,
                         z: z as u16,
+                        world_x: world[0],
+                        world_y: world[1],
+                        world_z: world[2],
+                        bounding_box: BoundingBox {
+                            min: world,
+                            max: [world[0] + pattern_width as i32 - 1, world[1] + pattern_height as i32 - 1, world[2] + pattern_length as i32 - 1],
+                        },
                         percent: (i_pattern_blocks - not_matching) as f32 / pattern_blocks,
                     });
                 }
@@ -0,0 +1,245 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize};
+use schemsearch_files::SpongeSchematic;
+use crate::{is_air, normalize_data};
+use crate::pattern_mapper::create_reverse_palette;
+
+const SIGNATURE_SIZE: usize = 64;
+const BAND_SIZE: usize = 4;
+const SHINGLE_SIZE: usize = 3;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Fingerprint {
+    pub blocks: u64,
+    pub histogram: BTreeMap<String, u64>,
+    pub signature: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DuplicateGroup {
+    pub members: Vec<usize>,
+    pub pairs: Vec<(usize, usize, f32)>,
+}
+
+pub fn fingerprint(schem: &SpongeSchematic, ignore_data: bool) -> Fingerprint {
+    let reverse_palette = create_reverse_palette(schem);
+    let names: Vec<&str> = reverse_palette.iter().map(|name| normalize_data(name, ignore_data)).collect();
+    let air: Vec<bool> = names.iter().map(|name| is_air(name)).collect();
+    let hashes: Vec<u64> = names.iter().map(|name| fnv1a(name.as_bytes())).collect();
+
+    let mut histogram = BTreeMap::new();
+    let mut blocks = 0;
+    for block in schem.block_data.iter() {
+        if !air[*block as usize] {
+            *histogram.entry(names[*block as usize].to_string()).or_insert(0) += 1;
+            blocks += 1;
+        }
+    }
+
+    let width = schem.width as usize;
+    let height = schem.height as usize;
+    let length = schem.length as usize;
+    let shingle_width = SHINGLE_SIZE.min(width);
+    let shingle_height = SHINGLE_SIZE.min(height);
+    let shingle_length = SHINGLE_SIZE.min(length);
+
+    let mut signature = vec![u64::MAX; SIGNATURE_SIZE];
+    if blocks > 0 {
+        for y in 0..=height - shingle_height {
+            for z in 0..=length - shingle_length {
+                for x in 0..=width - shingle_width {
+                    let mut hash = FNV_OFFSET;
+                    let mut empty = true;
+                    for j in 0..shingle_height {
+                        for k in 0..shingle_length {
+                            for i in 0..shingle_width {
+                                let block = schem.block_data[(x + i) + width * ((z + k) + (y + j) * length)] as usize;
+                                empty &= air[block];
+                                hash = (hash ^ hashes[block]).wrapping_mul(FNV_PRIME);
+                            }
+                        }
+                    }
+                    if empty {
+                        continue;
+                    }
+                    for (seed, min) in signature.iter_mut().enumerate() {
+                        *min = (*min).min(mix(hash ^ SEEDS[seed]));
+                    }
+                }
+            }
+        }
+    }
+
+    Fingerprint {
+        blocks,
+        histogram,
+        signature,
+    }
+}
+
+pub fn similarity(a: &Fingerprint, b: &Fingerprint) -> f32 {
+    if a.blocks == 0 || b.blocks == 0 {
+        return if a.blocks == b.blocks { 1.0 } else { 0.0 };
+    }
+    (histogram_similarity(a, b) + signature_similarity(a, b)) / 2.0
+}
+
+pub fn find_duplicates(fingerprints: &[Fingerprint], threshold: f32) -> Vec<DuplicateGroup> {
+    let mut buckets: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+    for (index, fingerprint) in fingerprints.iter().enumerate() {
+        if fingerprint.blocks == 0 {
+            continue;
+        }
+        for (band, rows) in fingerprint.signature.chunks(BAND_SIZE).enumerate() {
+            let hash = rows.iter().fold(FNV_OFFSET, |hash, row| (hash ^ row).wrapping_mul(FNV_PRIME));
+            buckets.entry((band, hash)).or_default().push(index);
+        }
+    }
+
+    let mut candidates: Vec<(usize, usize)> = buckets.values()
+        .flat_map(|bucket| bucket.iter().enumerate().flat_map(move |(i, a)| bucket[i + 1..].iter().map(move |b| (*a, *b))))
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let mut parents: Vec<usize> = (0..fingerprints.len()).collect();
+    let mut pairs = Vec::new();
+    for (a, b) in candidates {
+        let score = similarity(&fingerprints[a], &fingerprints[b]);
+        if score >= threshold {
+            let root_a = find_root(&mut parents, a);
+            let root_b = find_root(&mut parents, b);
+            parents[root_a.max(root_b)] = root_a.min(root_b);
+            pairs.push((a, b, score));
+        }
+    }
+
+    let mut groups: BTreeMap<usize, DuplicateGroup> = BTreeMap::new();
+    for (a, b, score) in pairs {
+        let root = find_root(&mut parents, a);
+        groups.entry(root).or_default().pairs.push((a, b, score));
+    }
+    for index in 0..fingerprints.len() {
+        let root = find_root(&mut parents, index);
+        if let Some(group) = groups.get_mut(&root) {
+            group.members.push(index);
+        }
+    }
+    groups.into_values().collect()
+}
+
+fn histogram_similarity(a: &Fingerprint, b: &Fingerprint) -> f32 {
+    let mut min = 0;
+    let mut max = 0;
+    for (block, count) in a.histogram.iter() {
+        let other = *b.histogram.get(block).unwrap_or(&0);
+        min += (*count).min(other);
+        max += (*count).max(other);
+    }
+    for (block, count) in b.histogram.iter() {
+        if !a.histogram.contains_key(block) {
+            max += *count;
+        }
+    }
+    min as f32 / max as f32
+}
+
+fn signature_similarity(a: &Fingerprint, b: &Fingerprint) -> f32 {
+    let equal = a.signature.iter().zip(b.signature.iter()).filter(|(a, b)| a == b).count();
+    equal as f32 / SIGNATURE_SIZE as f32
+}
+
+fn find_root(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[index] = root;
+    root
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+#[inline]
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(FNV_OFFSET, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+#[inline]
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+const SEEDS: [u64; SIGNATURE_SIZE] = {
+    let mut seeds = [0; SIGNATURE_SIZE];
+    let mut state: u64 = 0x9e3779b97f4a7c15;
+    let mut i = 0;
+    while i < SIGNATURE_SIZE {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        seeds[i] = state;
+        i += 1;
+    }
+    seeds
+};
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    #[test]
+    fn test_similarity_identical() {
+        let schem = SpongeSchematic::load(&PathBuf::from("../tests/Random.schem")).unwrap();
+        let a = fingerprint(&schem, false);
+        let b = fingerprint(&schem, false);
+
+        assert_eq!(similarity(&a, &b), 1.0);
+    }
+
+    #[test]
+    fn test_similarity_different() {
+        let a = fingerprint(&SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap(), false);
+        let b = fingerprint(&SpongeSchematic::load(&PathBuf::from("../tests/Random.schem")).unwrap(), false);
+
+        assert!(similarity(&a, &b) < 0.5);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let simple = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
+        let random = SpongeSchematic::load(&PathBuf::from("../tests/Random.schem")).unwrap();
+        let mut changed = simple.clone();
+        changed.block_data[0] = *changed.palette.get("minecraft:air").unwrap_or(&0);
+
+        let fingerprints = vec![
+            fingerprint(&simple, false),
+            fingerprint(&random, false),
+            fingerprint(&changed, false),
+        ];
+        let groups = find_duplicates(&fingerprints, 0.8);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].members, vec![0, 2]);
+    }
+}
@@ -0,0 +1,159 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use schemsearch_files::SpongeSchematic;
+use crate::{is_air, normalize_data, BoundingBox};
+use crate::pattern_mapper::create_reverse_palette;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SchematicStats {
+    pub width: u16,
+    pub height: u16,
+    pub length: u16,
+    /// Number of non-air blocks
+    pub volume: u64,
+    /// Tight bounds of all non-air blocks in schematic coordinates, `None` if the schematic is empty
+    pub bounding_box: Option<BoundingBox>,
+    /// Counts by full block state, e.g. `minecraft:repeater[delay=2,facing=north]`
+    pub blocks: BTreeMap<String, u64>,
+    /// Counts by block id, ignoring the block state
+    pub block_types: BTreeMap<String, u64>,
+    pub block_entities: BTreeMap<String, u64>,
+}
+
+impl SchematicStats {
+    /// Looks up a block state if `block` contains one, otherwise the block id
+    pub fn count(&self, block: &str) -> u64 {
+        if block.contains('[') {
+            self.blocks.get(block).copied().unwrap_or(0)
+        } else {
+            self.block_types.get(block).copied().unwrap_or(0)
+        }
+    }
+}
+
+pub fn stats(schem: &SpongeSchematic) -> SchematicStats {
+    let reverse_palette = create_reverse_palette(schem);
+    let mut counts = vec![0u64; reverse_palette.len()];
+
+    let width = schem.width as usize;
+    let length = schem.length as usize;
+    let mut min = [i32::MAX; 3];
+    let mut max = [i32::MIN; 3];
+    let mut volume = 0;
+
+    for (i, block) in schem.block_data.iter().enumerate() {
+        let name = match reverse_palette.get(*block as usize) {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        counts[*block as usize] += 1;
+        if is_air(normalize_data(name, true)) {
+            continue;
+        }
+        volume += 1;
+        // i = x + z * Width + y * Width * Length
+        let pos = [(i % width) as i32, (i / (width * length)) as i32, ((i / width) % length) as i32];
+        for axis in 0..3 {
+            min[axis] = min[axis].min(pos[axis]);
+            max[axis] = max[axis].max(pos[axis]);
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    let mut block_types = BTreeMap::new();
+    for (id, count) in counts.into_iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let name = reverse_palette[id];
+        *blocks.entry(name.to_string()).or_insert(0) += count;
+        *block_types.entry(normalize_data(name, true).to_string()).or_insert(0) += count;
+    }
+
+    let mut block_entities = BTreeMap::new();
+    for entity in schem.block_entities.iter() {
+        *block_entities.entry(entity.id.clone()).or_insert(0) += 1;
+    }
+
+    SchematicStats {
+        width: schem.width,
+        height: schem.height,
+        length: schem.length,
+        volume,
+        bounding_box: if volume == 0 { None } else { Some(BoundingBox { min, max }) },
+        blocks,
+        block_types,
+        block_entities,
+    }
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use nbt::CompoundTag;
+    use schemsearch_files::BlockEntity;
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let schem = SpongeSchematic {
+            data_version: 1,
+            metadata: CompoundTag::new(),
+            width: 2,
+            height: 2,
+            length: 2,
+            offset: [0, 0, 0],
+            palette_max: 4,
+            palette: vec![
+                ("minecraft:air".to_owned(), 0),
+                ("minecraft:tnt".to_owned(), 1),
+                ("minecraft:dispenser[facing=north]".to_owned(), 2),
+                ("minecraft:dispenser[facing=up]".to_owned(), 3),
+            ].into_iter().collect::<HashMap<String, i32>>(),
+            block_data: vec![0, 1, 0, 0, 0, 2, 3, 0],
+            block_entities: vec![
+                BlockEntity { id: "minecraft:dispenser".to_owned(), pos: [1, 1, 0] },
+                BlockEntity { id: "minecraft:dispenser".to_owned(), pos: [0, 1, 1] },
+            ],
+            entities: None,
+        };
+        let stats = stats(&schem);
+
+        assert_eq!(stats.volume, 3);
+        assert_eq!(stats.count("minecraft:tnt"), 1);
+        assert_eq!(stats.count("minecraft:dispenser"), 2);
+        assert_eq!(stats.count("minecraft:dispenser[facing=up]"), 1);
+        assert_eq!(stats.count("minecraft:air"), 5);
+        assert_eq!(stats.block_entities.get("minecraft:dispenser"), Some(&2));
+        assert_eq!(stats.bounding_box, Some(BoundingBox { min: [0, 0, 0], max: [1, 1, 1] }));
+    }
+
+    #[test]
+    fn test_stats_schematic() {
+        let schematic = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
+        let stats = stats(&schematic);
+
+        assert_eq!(stats.blocks.values().sum::<u64>(), schematic.block_data.len() as u64);
+        assert_eq!(stats.block_types.values().sum::<u64>(), schematic.block_data.len() as u64);
+        assert!(stats.volume <= schematic.block_data.len() as u64);
+    }
+}
@@ -0,0 +1,320 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use schemsearch_files::SpongeSchematic;
+use crate::nbt_search::nbt_diagnostics;
+use crate::search::search;
+use crate::stats::stats;
+use crate::SearchBehavior;
+
+const TNT: &str = "minecraft:tnt";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    pub max_width: Option<u16>,
+    pub max_height: Option<u16>,
+    pub max_length: Option<u16>,
+    #[serde(default)]
+    pub forbidden_blocks: Vec<String>,
+    /// Maximum count per block id or block state
+    #[serde(default)]
+    pub block_limits: BTreeMap<String, u64>,
+    pub tnt_limit: Option<u64>,
+    #[serde(default = "default_true")]
+    pub check_nbt: bool,
+    #[serde(default)]
+    pub banned_patterns: Vec<PatternRule>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatternRule {
+    pub name: Option<String>,
+    /// Relative paths are resolved against the directory of the rule file
+    pub path: PathBuf,
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    #[serde(default)]
+    pub ignore_data: bool,
+    #[serde(default)]
+    pub ignore_air: bool,
+    #[serde(default)]
+    pub air_as_any: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            max_width: None,
+            max_height: None,
+            max_length: None,
+            forbidden_blocks: Vec::new(),
+            block_limits: BTreeMap::new(),
+            tnt_limit: None,
+            check_nbt: true,
+            banned_patterns: Vec::new(),
+        }
+    }
+}
+
+impl Rules {
+    pub fn from_toml(data: &str) -> Result<Rules, String> {
+        toml::from_str(data).map_err(|e| e.to_string())
+    }
+
+    pub fn from_yaml(data: &str) -> Result<Rules, String> {
+        serde_yaml::from_str(data).map_err(|e| e.to_string())
+    }
+
+    /// Picks the format by the file extension, `.yml` and `.yaml` are read as YAML, everything else as TOML
+    pub fn load(path: &Path) -> Result<Rules, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("yml") | Some("yaml") => Rules::from_yaml(&data),
+            _ => Rules::from_toml(&data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Violation {
+    pub rule: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ValidationReport {
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    fn push(&mut self, rule: &str, message: String) {
+        self.violations.push(Violation { rule: rule.to_string(), message });
+    }
+}
+
+pub struct Validator {
+    rules: Rules,
+    patterns: Vec<(String, SpongeSchematic, SearchBehavior)>,
+}
+
+impl Validator {
+    /// Loads the banned patterns, resolving relative paths against `base`
+    pub fn new(rules: Rules, base: &Path) -> Result<Validator, String> {
+        let mut patterns = Vec::with_capacity(rules.banned_patterns.len());
+        for rule in rules.banned_patterns.iter() {
+            let path = base.join(&rule.path);
+            let pattern = SpongeSchematic::load(&path).map_err(|e| format!("Could not load pattern {}: {}", path.display(), e))?;
+            let name = rule.name.clone().unwrap_or_else(|| rule.path.display().to_string());
+            patterns.push((name, pattern, SearchBehavior {
+                ignore_block_data: rule.ignore_data,
+                ignore_block_entities: true,
+                ignore_air: rule.ignore_air,
+                air_as_any: rule.air_as_any,
+                ignore_entities: true,
+                threshold: rule.threshold,
+                invalid_nbt: false,
+            }));
+        }
+        Ok(Validator { rules, patterns })
+    }
+
+    pub fn validate(&self, schem: &SpongeSchematic) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        check_structure(schem, &mut report);
+        // The other checks index the block data with the palette ids and can't run on a broken schematic
+        if !report.passed() {
+            return report;
+        }
+
+        for (rule, size, max) in [
+            ("max_width", schem.width, self.rules.max_width),
+            ("max_height", schem.height, self.rules.max_height),
+            ("max_length", schem.length, self.rules.max_length),
+        ] {
+            if let Some(max) = max {
+                if size > max {
+                    report.push(rule, format!("is {} blocks but at most {} are allowed", size, max));
+                }
+            }
+        }
+
+        let stats = stats(schem);
+        for block in self.rules.forbidden_blocks.iter() {
+            let count = stats.count(block);
+            if count > 0 {
+                report.push("forbidden_blocks", format!("contains {} {} which is forbidden", count, block));
+            }
+        }
+        for (block, limit) in self.rules.block_limits.iter() {
+            let count = stats.count(block);
+            if count > *limit {
+                report.push("block_limits", format!("contains {} {} but at most {} are allowed", count, block, limit));
+            }
+        }
+        if let Some(limit) = self.rules.tnt_limit {
+            let count = stats.count(TNT);
+            if count > limit {
+                report.push("tnt_limit", format!("contains {} TNT but at most {} are allowed", count, limit));
+            }
+        }
+
+        if self.rules.check_nbt {
+            for issue in nbt_diagnostics(schem) {
+                report.push("check_nbt", issue.to_string());
+            }
+        }
+
+        for (name, pattern, search_behavior) in self.patterns.iter() {
+            let matches = search(schem.clone(), pattern, *search_behavior);
+            if let Some(first) = matches.first() {
+                report.push("banned_patterns", format!("contains {} {} times, first at {},{},{}", name, matches.len(), first.world_x, first.world_y, first.world_z));
+            }
+        }
+
+        report
+    }
+}
+
+fn check_structure(schem: &SpongeSchematic, report: &mut ValidationReport) {
+    let volume = schem.width as usize * schem.height as usize * schem.length as usize;
+    if schem.block_data.len() != volume {
+        report.push("structure", format!("has {} blocks but its dimensions need {}", schem.block_data.len(), volume));
+    }
+
+    let ids = schem.palette.values().copied().collect::<HashSet<i32>>();
+    let unknown = schem.block_data.iter().filter(|id| !ids.contains(id)).collect::<HashSet<&i32>>();
+    if !unknown.is_empty() {
+        report.push("structure", format!("uses {} block ids missing from the palette", unknown.len()));
+    }
+
+    for entity in schem.block_entities.iter() {
+        let [x, y, z] = entity.pos;
+        if x < 0 || y < 0 || z < 0 || x >= schem.width as i32 || y >= schem.height as i32 || z >= schem.length as i32 {
+            report.push("structure", format!("block entity {} at {},{},{} is outside the schematic", entity.id, x, y, z));
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_threshold() -> f32 {
+    0.9
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        let toml = Rules::from_toml(r#"
+            max_width = 20
+            forbidden_blocks = ["minecraft:bedrock"]
+            tnt_limit = 100
+
+            [block_limits]
+            "minecraft:dispenser" = 10
+
+            [[banned_patterns]]
+            path = "endstone.schem"
+        "#).unwrap();
+        let yaml = Rules::from_yaml(r#"
+            max_width: 20
+            forbidden_blocks: [minecraft:bedrock]
+            tnt_limit: 100
+            block_limits:
+              minecraft:dispenser: 10
+            banned_patterns:
+              - path: endstone.schem
+        "#).unwrap();
+
+        for rules in [toml, yaml] {
+            assert_eq!(rules.max_width, Some(20));
+            assert_eq!(rules.tnt_limit, Some(100));
+            assert_eq!(rules.block_limits.get("minecraft:dispenser"), Some(&10));
+            assert!(rules.check_nbt);
+            assert_eq!(rules.banned_patterns[0].threshold, 0.9);
+        }
+        assert!(Rules::from_toml("max_widht = 20").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let schematic = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
+        let rules = Rules::from_toml(r#"
+            max_width = 20
+            forbidden_blocks = ["minecraft:end_stone"]
+            tnt_limit = 0
+
+            [block_limits]
+            "minecraft:andesite" = 1000
+
+            [[banned_patterns]]
+            name = "endstone"
+            path = "endstone.schem"
+        "#).unwrap();
+        let report = Validator::new(rules, Path::new("../tests")).unwrap().validate(&schematic);
+
+        let rules: Vec<&str> = report.violations.iter().map(|v| v.rule.as_str()).collect();
+        assert_eq!(rules, vec!["max_width", "forbidden_blocks", "block_limits", "banned_patterns"]);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn test_validate_default() {
+        let schematic = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
+        let report = Validator::new(Rules::default(), Path::new("")).unwrap().validate(&schematic);
+
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn test_validate_broken_structure() {
+        let rules = Rules::from_toml(r#"
+            tnt_limit = 0
+
+            [[banned_patterns]]
+            path = "endstone.schem"
+        "#).unwrap();
+        let validator = Validator::new(rules, Path::new("../tests")).unwrap();
+        let schematic = SpongeSchematic::load(&PathBuf::from("../tests/simple.schem")).unwrap();
+
+        let mut truncated = schematic.clone();
+        truncated.block_data.truncate(truncated.block_data.len() / 2);
+        let mut unknown = schematic;
+        unknown.block_data[0] = i32::MAX;
+
+        for schematic in [truncated, unknown] {
+            let report = validator.validate(&schematic);
+            let rules: Vec<&str> = report.violations.iter().map(|v| v.rule.as_str()).collect();
+            assert_eq!(rules, vec!["structure"]);
+        }
+    }
+}
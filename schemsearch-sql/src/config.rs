@@ -0,0 +1,374 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use sqlx::mysql::{MySqlConnectOptions, MySqlPoolOptions, MySqlSslMode};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions, PgSslMode};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use crate::mapping::SchemaMapping;
+use crate::storage::{AnyStorage, SqlStorage, SqliteStorage};
+
+pub const DEFAULT_POOL_SIZE: u32 = 5;
+/// Read when no other config file is given, for compatibility with the SteamWar setup
+pub const LEGACY_CONFIG_FILE: &str = "mysql.properties";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsMode {
+    Disable,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl FromStr for TlsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "disable" | "disabled" | "false" => Ok(TlsMode::Disable),
+            "prefer" | "preferred" => Ok(TlsMode::Prefer),
+            "require" | "required" | "true" => Ok(TlsMode::Require),
+            "verify-ca" | "verify_ca" => Ok(TlsMode::VerifyCa),
+            "verify-full" | "verify_full" | "verify-identity" | "verify_identity" => Ok(TlsMode::VerifyFull),
+            _ => Err(format!("Invalid TLS mode '{}', expected disable, prefer, require, verify-ca or verify-full", s)),
+        }
+    }
+}
+
+impl From<TlsMode> for MySqlSslMode {
+    fn from(mode: TlsMode) -> Self {
+        match mode {
+            TlsMode::Disable => MySqlSslMode::Disabled,
+            TlsMode::Prefer => MySqlSslMode::Preferred,
+            TlsMode::Require => MySqlSslMode::Required,
+            TlsMode::VerifyCa => MySqlSslMode::VerifyCa,
+            TlsMode::VerifyFull => MySqlSslMode::VerifyIdentity,
+        }
+    }
+}
+
+impl From<TlsMode> for PgSslMode {
+    fn from(mode: TlsMode) -> Self {
+        match mode {
+            TlsMode::Disable => PgSslMode::Disable,
+            TlsMode::Prefer => PgSslMode::Prefer,
+            TlsMode::Require => PgSslMode::Require,
+            TlsMode::VerifyCa => PgSslMode::VerifyCa,
+            TlsMode::VerifyFull => PgSslMode::VerifyFull,
+        }
+    }
+}
+
+/// Connection settings, every value that is set overrides the matching part of `url`.
+/// Without a `url`, `host` connects to MySQL.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionConfig {
+    pub url: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub database: Option<String>,
+    pub pool_size: Option<u32>,
+    pub tls: Option<TlsMode>,
+    pub mapping: Option<SchemaMapping>,
+}
+
+impl ConnectionConfig {
+    pub const KEYS: [&'static str; 8] = ["url", "host", "port", "user", "password", "database", "pool_size", "tls"];
+
+    pub fn from_url(url: &str) -> ConnectionConfig {
+        ConnectionConfig {
+            url: Some(url.to_string()),
+            ..ConnectionConfig::default()
+        }
+    }
+
+    /// Parses `key=value` lines, blank lines and lines starting with `#` are skipped
+    pub fn from_properties(content: &str) -> Result<ConnectionConfig, String> {
+        ConnectionConfig::parse_properties(content, None)
+    }
+
+    /// Like `from_properties`, but unknown keys are skipped and added to `ignored`
+    fn parse_properties(content: &str, mut ignored: Option<&mut Vec<String>>) -> Result<ConnectionConfig, String> {
+        let mut config = ConnectionConfig::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| format!("Line {}: expected 'key=value'", number + 1))?;
+            let key = key.trim();
+            if let Some(ignored) = ignored.as_mut() {
+                if !ConnectionConfig::KEYS.contains(&key) && !SchemaMapping::KEYS.contains(&key) {
+                    ignored.push(format!("Line {}: Unknown setting '{}'", number + 1, key));
+                    continue;
+                }
+            }
+            config.set(key, value.trim()).map_err(|e| format!("Line {}: {}", number + 1, e))?;
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<ConnectionConfig, String> {
+        let content = read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        ConnectionConfig::from_properties(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Reads `~/mysql.properties`, which is shared with other programs, so unknown keys are ignored and returned as warnings
+    pub fn from_legacy_file(path: &Path) -> Result<(ConnectionConfig, Vec<String>), String> {
+        let content = read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut ignored = Vec::new();
+        let config = ConnectionConfig::parse_properties(&content, Some(&mut ignored)).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok((config, ignored.into_iter().map(|e| format!("{}: {}, ignoring it", path.display(), e)).collect()))
+    }
+
+    /// Reads `SCHEMSEARCH_SQL_URL`, `SCHEMSEARCH_SQL_HOST`, `SCHEMSEARCH_SQL_PORT`, `SCHEMSEARCH_SQL_USER`,
+    /// `SCHEMSEARCH_SQL_PASSWORD`, `SCHEMSEARCH_SQL_DATABASE`, `SCHEMSEARCH_SQL_POOL_SIZE` and `SCHEMSEARCH_SQL_TLS`
+    pub fn from_env() -> Result<ConnectionConfig, String> {
+        ConnectionConfig::from_vars(|key| std::env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<ConnectionConfig, String> {
+        let mut config = ConnectionConfig::default();
+        for key in ConnectionConfig::KEYS {
+            let name = format!("SCHEMSEARCH_SQL_{}", key.to_uppercase());
+            if let Some(value) = var(&name) {
+                config.set(key, &value).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Combines the config file, the environment and `overrides`, later ones taking precedence.
+    /// The config file is `path`, `SCHEMSEARCH_SQL_CONFIG` or `~/mysql.properties` if it exists.
+    /// Also returns the warnings of `from_legacy_file`.
+    pub fn load(path: Option<&Path>, overrides: ConnectionConfig) -> Result<(ConnectionConfig, Vec<String>), String> {
+        let path = path.map(Path::to_path_buf)
+            .or_else(|| std::env::var_os("SCHEMSEARCH_SQL_CONFIG").map(PathBuf::from));
+        let legacy = std::env::var_os("HOME")
+            .map(|home| Path::new(&home).join(LEGACY_CONFIG_FILE))
+            .filter(|path| path.exists());
+        let (file, warnings) = match (path, legacy) {
+            (Some(path), _) => (ConnectionConfig::from_file(&path)?, Vec::new()),
+            (None, Some(legacy)) => ConnectionConfig::from_legacy_file(&legacy)?,
+            (None, None) => (ConnectionConfig::default(), Vec::new()),
+        };
+        Ok((file.merge(ConnectionConfig::from_env()?).merge(overrides), warnings))
+    }
+
+    /// Returns `self` with every value set in `other` replaced.
+    /// A url in `other` also drops the host, port, user, password and database of `self`, `connect` would apply them on top of the url.
+    pub fn merge(mut self, other: ConnectionConfig) -> ConnectionConfig {
+        if other.url.is_some() {
+            self.host = None;
+            self.port = None;
+            self.user = None;
+            self.password = None;
+            self.database = None;
+        }
+        ConnectionConfig {
+            url: other.url.or(self.url),
+            host: other.host.or(self.host),
+            port: other.port.or(self.port),
+            user: other.user.or(self.user),
+            password: other.password.or(self.password),
+            database: other.database.or(self.database),
+            pool_size: other.pool_size.or(self.pool_size),
+            tls: other.tls.or(self.tls),
+            mapping: other.mapping.or(self.mapping),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "url" => self.url = Some(value.to_string()),
+            "host" => self.host = Some(value.to_string()),
+            "port" => self.port = Some(value.parse().map_err(|_| format!("Invalid port '{}'", value))?),
+            "user" => self.user = Some(value.to_string()),
+            "password" => self.password = Some(value.to_string()),
+            "database" => self.database = Some(value.to_string()),
+            "pool_size" => self.pool_size = Some(match value.parse() {
+                Ok(0) | Err(_) => return Err(format!("Invalid pool size '{}'", value)),
+                Ok(x) => x,
+            }),
+            "tls" => self.tls = Some(value.parse()?),
+            _ if SchemaMapping::KEYS.contains(&key) => self.mapping.get_or_insert_with(SchemaMapping::default).set(key, value)?,
+            _ => return Err(format!("Unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    pub fn scheme(&self) -> Result<&str, String> {
+        match &self.url {
+            Some(url) => Ok(url.split(':').next().unwrap_or_default()),
+            None if self.host.is_some() => Ok("mysql"),
+            None => Err("No database configured, expected a url or a host".to_string()),
+        }
+    }
+
+    pub async fn connect(&self) -> Result<AnyStorage, String> {
+        let pool_size = self.pool_size.unwrap_or(DEFAULT_POOL_SIZE);
+        let mapping = self.mapping.clone().unwrap_or_default();
+        mapping.validate()?;
+        match self.scheme()? {
+            "mysql" | "mariadb" => {
+                let mut options = match &self.url {
+                    Some(url) => MySqlConnectOptions::from_str(url).map_err(|e| format!("Invalid database url: {}", e))?,
+                    None => MySqlConnectOptions::new(),
+                };
+                if let Some(host) = &self.host { options = options.host(host); }
+                if let Some(port) = self.port { options = options.port(port); }
+                if let Some(user) = &self.user { options = options.username(user); }
+                if let Some(password) = &self.password { options = options.password(password); }
+                if let Some(database) = &self.database { options = options.database(database); }
+                if let Some(tls) = self.tls { options = options.ssl_mode(tls.into()); }
+                let pool = MySqlPoolOptions::new()
+                    .max_connections(pool_size)
+                    .connect_with(options)
+                    .await.map_err(|e| format!("Failed to connect to database: {}", e))?;
+                Ok(AnyStorage::MySql(SqlStorage::new(pool).with_mapping(mapping)))
+            }
+            "postgres" | "postgresql" => {
+                let mut options = match &self.url {
+                    Some(url) => PgConnectOptions::from_str(url).map_err(|e| format!("Invalid database url: {}", e))?,
+                    None => PgConnectOptions::new(),
+                };
+                if let Some(host) = &self.host { options = options.host(host); }
+                if let Some(port) = self.port { options = options.port(port); }
+                if let Some(user) = &self.user { options = options.username(user); }
+                if let Some(password) = &self.password { options = options.password(password); }
+                if let Some(database) = &self.database { options = options.database(database); }
+                if let Some(tls) = self.tls { options = options.ssl_mode(tls.into()); }
+                let pool = PgPoolOptions::new()
+                    .max_connections(pool_size)
+                    .connect_with(options)
+                    .await.map_err(|e| format!("Failed to connect to database: {}", e))?;
+                Ok(AnyStorage::Postgres(SqlStorage::new(pool).with_mapping(mapping)))
+            }
+            "sqlite" => {
+                let url = self.url.as_deref().unwrap_or_default();
+                let options = SqliteConnectOptions::from_str(url).map_err(|e| format!("Invalid database url: {}", e))?;
+                let storage = SqliteStorage::open_with(options, SqlitePoolOptions::new().max_connections(pool_size)).await?;
+                Ok(AnyStorage::Sqlite(storage.with_mapping(mapping)))
+            }
+            scheme => Err(format!("Unsupported database url scheme '{}', expected mysql, postgres or sqlite", scheme)),
+        }
+    }
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use futures::executor::block_on;
+    use super::*;
+
+    #[test]
+    fn test_properties() {
+        let config = ConnectionConfig::from_properties("# SteamWar\nhost=db.example.org\nuser = steamwar\npassword=a=b==c\n\ndatabase=core\nport=3307\ntls=required\n").unwrap();
+
+        assert_eq!(config, ConnectionConfig {
+            url: None,
+            host: Some("db.example.org".to_string()),
+            port: Some(3307),
+            user: Some("steamwar".to_string()),
+            password: Some("a=b==c".to_string()),
+            database: Some("core".to_string()),
+            pool_size: None,
+            tls: Some(TlsMode::Require),
+            mapping: None,
+        });
+
+        let config = ConnectionConfig::from_properties("url=sqlite://schematics.db\nnode_table=schematics\nsponge_format=ND.format = 'sponge'").unwrap();
+        let mapping = config.mapping.unwrap();
+        assert_eq!(mapping.node_table, "schematics");
+        assert_eq!(mapping.data_table, "NodeData");
+        assert_eq!(mapping.sponge_format, "ND.format = 'sponge'");
+    }
+
+    #[test]
+    fn test_properties_errors() {
+        assert_eq!(ConnectionConfig::from_properties("host").unwrap_err(), "Line 1: expected 'key=value'");
+        assert_eq!(ConnectionConfig::from_properties("host=a\nport=abc").unwrap_err(), "Line 2: Invalid port 'abc'");
+        assert_eq!(ConnectionConfig::from_properties("pool_size=0").unwrap_err(), "Line 1: Invalid pool size '0'");
+        assert_eq!(ConnectionConfig::from_properties("colour=red").unwrap_err(), "Line 1: Unknown setting 'colour'");
+        assert_eq!(ConnectionConfig::from_properties("data_table=a b").unwrap_err(), "Line 1: Invalid table or column name 'a b'");
+        assert!(ConnectionConfig::from_properties("tls=sometimes").is_err());
+    }
+
+    #[test]
+    fn test_legacy_file() {
+        let path = std::env::temp_dir().join(format!("schemsearch-legacy-{}.properties", std::process::id()));
+        std::fs::write(&path, "host=legacy-host\nwebhook=https://example.org\nnode_table=schematics\n").unwrap();
+
+        let (config, warnings) = ConnectionConfig::from_legacy_file(&path).unwrap();
+        assert_eq!(config.host.as_deref(), Some("legacy-host"));
+        assert_eq!(config.mapping.unwrap().node_table, "schematics");
+        assert_eq!(warnings, vec![format!("{}: Line 2: Unknown setting 'webhook', ignoring it", path.display())]);
+        assert!(ConnectionConfig::from_file(&path).is_err());
+
+        std::fs::write(&path, "port=abc\n").unwrap();
+        assert!(ConnectionConfig::from_legacy_file(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_env_and_merge() {
+        let vars: HashMap<&str, &str> = [("SCHEMSEARCH_SQL_HOST", "env-host"), ("SCHEMSEARCH_SQL_POOL_SIZE", "16")].into_iter().collect();
+        let env = ConnectionConfig::from_vars(|key| vars.get(key).map(|x| x.to_string())).unwrap();
+        let file = ConnectionConfig::from_properties("host=file-host\nuser=file-user").unwrap();
+        let flags = ConnectionConfig { port: Some(5432), ..ConnectionConfig::default() };
+        let config = file.merge(env).merge(flags);
+
+        assert_eq!(config.host.as_deref(), Some("env-host"));
+        assert_eq!(config.user.as_deref(), Some("file-user"));
+        assert_eq!(config.pool_size, Some(16));
+        assert_eq!(config.port, Some(5432));
+
+        let legacy = ConnectionConfig::from_properties("host=legacy-host\nuser=legacy-user\npassword=legacy\ndatabase=legacy-db\npool_size=2").unwrap();
+        let flags = ConnectionConfig { url: Some("mysql://other/db".to_string()), ..ConnectionConfig::default() };
+        let config = legacy.clone().merge(flags);
+        assert_eq!(config.url.as_deref(), Some("mysql://other/db"));
+        assert_eq!((config.host, config.user, config.password, config.database), (None, None, None, None));
+        assert_eq!(config.pool_size, Some(2));
+
+        let env = ConnectionConfig::from_properties("url=postgres://env/db\npassword=secret").unwrap();
+        let flags = ConnectionConfig { database: Some("other".to_string()), ..ConnectionConfig::default() };
+        let config = legacy.merge(env).merge(flags);
+        assert_eq!(config.host, None);
+        assert_eq!(config.password.as_deref(), Some("secret"));
+        assert_eq!(config.database.as_deref(), Some("other"));
+
+        let vars: HashMap<&str, &str> = [("SCHEMSEARCH_SQL_PORT", "-1")].into_iter().collect();
+        assert_eq!(ConnectionConfig::from_vars(|key| vars.get(key).map(|x| x.to_string())).unwrap_err(), "SCHEMSEARCH_SQL_PORT: Invalid port '-1'");
+    }
+
+    #[test]
+    fn test_connect_errors() {
+        block_on(async {
+            assert_eq!(ConnectionConfig::default().connect().await.err().unwrap(), "No database configured, expected a url or a host");
+            assert!(ConnectionConfig::from_url("oracle://localhost/schematics").connect().await.is_err());
+            assert!(matches!(ConnectionConfig::from_url("sqlite::memory:").connect().await, Ok(AnyStorage::Sqlite(_))));
+        });
+    }
+}
@@ -1,7 +1,18 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use sqlx::{Database, Encode, QueryBuilder, Type};
+use crate::mapping::SchemaMapping;
+
 #[derive(Default, Debug, Clone)]
 pub struct SchematicFilter {
     pub user_id: Option<Vec<u32>>,
     pub name: Option<Vec<String>>,
+    pub node_type: Option<Vec<String>>,
+    pub modified_after: Option<NaiveDateTime>,
+    /// Like `modified_after`, but includes nodes modified at that time
+    pub modified_since: Option<NaiveDateTime>,
+    /// Folder nodes, matches everything inside them recursively, cycles in the parent column are visited once
+    pub parent: Option<Vec<i32>>,
+    pub id: Option<Vec<i32>>,
 }
 
 impl SchematicFilter {
@@ -9,6 +20,11 @@ impl SchematicFilter {
         SchematicFilter {
             user_id: None,
             name: None,
+            node_type: None,
+            modified_after: None,
+            modified_since: None,
+            parent: None,
+            id: None,
         }
     }
 
@@ -22,17 +38,185 @@ impl SchematicFilter {
         self
     }
 
-    pub fn build(self) -> String {
-        if self.user_id.is_none() && self.name.is_none() {
-            return String::new();
+    pub fn node_type(mut self, node_type: Vec<&String>) -> SchematicFilter {
+        self.node_type = Some(node_type.into_iter().map(|node_type| node_type.to_string()).collect());
+        self
+    }
+
+    pub fn modified_after(mut self, modified_after: NaiveDateTime) -> SchematicFilter {
+        self.modified_after = Some(modified_after);
+        self
+    }
+
+    pub fn modified_since(mut self, modified_since: NaiveDateTime) -> SchematicFilter {
+        self.modified_since = Some(modified_since);
+        self
+    }
+
+    pub fn parent(mut self, parent: Vec<&i32>) -> SchematicFilter {
+        self.parent = Some(parent.into_iter().copied().collect());
+        self
+    }
+
+    pub fn id(mut self, id: Vec<&i32>) -> SchematicFilter {
+        self.id = Some(id.into_iter().copied().collect());
+        self
+    }
+
+    /// Appends the filter as `AND (...)` conditions, all values are passed as bind parameters
+    pub fn push_conditions<'args, DB: Database>(&self, mapping: &SchemaMapping, query: &mut QueryBuilder<'args, DB>)
+    where
+        i32: Encode<'args, DB> + Type<DB>,
+        i64: Encode<'args, DB> + Type<DB>,
+        String: Encode<'args, DB> + Type<DB>,
+        NaiveDateTime: Encode<'args, DB> + Type<DB>,
+    {
+        if let Some(user_id) = self.user_id.as_ref().filter(|x| !x.is_empty()) {
+            query.push(" AND (");
+            let mut separated = query.separated(" OR ");
+            for id in user_id {
+                separated.push(format!("SN.{} = ", mapping.owner_column)).push_bind_unseparated(i64::from(*id));
+            }
+            query.push(")");
+        }
+        if let Some(name) = self.name.as_ref().filter(|x| !x.is_empty()) {
+            query.push(" AND (");
+            let mut separated = query.separated(" OR ");
+            for name in name {
+                separated.push(format!("SN.{} LIKE ", mapping.name_column)).push_bind_unseparated(like_pattern(name)).push_unseparated(" ESCAPE '!'");
+            }
+            query.push(")");
+        }
+        if let Some(node_type) = self.node_type.as_ref().filter(|x| !x.is_empty()) {
+            query.push(format!(" AND SN.{} IN (", mapping.type_column));
+            push_list(query, node_type.iter().cloned());
+            query.push(")");
+        }
+        if let Some(modified_after) = self.modified_after {
+            query.push(format!(" AND SN.{} > ", mapping.modified_column)).push_bind(modified_after);
         }
-        let mut query = Vec::new();
-        if let Some(user_id) = self.user_id {
-            query.push(user_id.into_iter().map(|id| format!("SN.NodeOwner = {}", id)).collect::<Vec<String>>().join(" OR "));
+        if let Some(modified_since) = self.modified_since {
+            query.push(format!(" AND SN.{} >= ", mapping.modified_column)).push_bind(modified_since);
         }
-        if let Some(name) = self.name {
-            query.push(name.into_iter().map(|name| format!("SN.NodeName LIKE '%{}%'", name)).collect::<Vec<String>>().join(" OR "));
+        if let Some(parent) = self.parent.as_ref().filter(|x| !x.is_empty()) {
+            query.push(format!(
+                " AND SN.{id} IN (WITH RECURSIVE folder (id) AS (SELECT {id} FROM {table} WHERE {id} IN (",
+                id = mapping.id_column, table = mapping.node_table,
+            ));
+            push_list(query, parent.iter().copied());
+            query.push(format!(
+                ") UNION SELECT C.{id} FROM {table} C INNER JOIN folder F ON C.{parent} = F.id) SELECT id FROM folder)",
+                id = mapping.id_column, table = mapping.node_table, parent = mapping.parent_column,
+            ));
         }
-        format!("AND ({})", query.join(") AND ("))
+        if let Some(id) = self.id.as_ref().filter(|x| !x.is_empty()) {
+            query.push(format!(" AND SN.{} IN (", mapping.id_column));
+            push_list(query, id.iter().copied());
+            query.push(")");
+        }
+    }
+}
+
+pub(crate) fn push_list<'args, DB: Database, T>(query: &mut QueryBuilder<'args, DB>, values: impl Iterator<Item = T>)
+where
+    T: 'args + Encode<'args, DB> + Send + Type<DB>,
+{
+    let mut separated = query.separated(", ");
+    for value in values {
+        separated.push_bind(value);
     }
-}
\ No newline at end of file
+}
+
+/// Parses `YYYY-MM-DD`, `YYYY-MM-DD HH:MM:SS` or `YYYY-MM-DDTHH:MM:SS`, compared to the database values without a time zone
+pub fn parse_date(date: &str) -> Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap()))
+        .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS", date))
+}
+
+/// Matches `name` anywhere in the column, with the LIKE wildcards in it escaped by `!`
+/// (SQLite has no default escape character, so the conditions use `ESCAPE '!'`)
+pub fn like_pattern(name: &str) -> String {
+    let mut pattern = String::with_capacity(name.len() + 2);
+    pattern.push('%');
+    for c in name.chars() {
+        if matches!(c, '!' | '%' | '_') {
+            pattern.push('!');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use sqlx::{MySql, Postgres};
+    use super::*;
+
+    fn build(filter: &SchematicFilter) -> String {
+        let mut query = QueryBuilder::<MySql>::new("SELECT * FROM SchematicNode SN WHERE true");
+        filter.push_conditions(&SchemaMapping::default(), &mut query);
+        query.into_sql()
+    }
+
+    #[test]
+    fn test_empty_filter() {
+        assert_eq!(build(&SchematicFilter::default()), "SELECT * FROM SchematicNode SN WHERE true");
+        assert_eq!(build(&SchematicFilter { user_id: Some(vec![]), name: Some(vec![]), node_type: Some(vec![]), modified_after: None, modified_since: None, parent: Some(vec![]), id: Some(vec![]) }), "SELECT * FROM SchematicNode SN WHERE true");
+    }
+
+    #[test]
+    fn test_filter_binds_values() {
+        let hostile = "x' OR '1'='1".to_string();
+        let other = "Robert'); DROP TABLE SchematicNode;--".to_string();
+        let filter = SchematicFilter::new().user_id(vec![&1, &2]).name(vec![&hostile, &other]);
+        let sql = build(&filter);
+
+        assert_eq!(sql, "SELECT * FROM SchematicNode SN WHERE true AND (SN.NodeOwner = ? OR SN.NodeOwner = ?) AND (SN.NodeName LIKE ? ESCAPE '!' OR SN.NodeName LIKE ? ESCAPE '!')");
+        assert!(!sql.contains(&hostile));
+        assert!(!sql.contains(&other));
+    }
+
+    #[test]
+    fn test_filter_postgres_placeholders() {
+        let name = "Castle".to_string();
+        let filter = SchematicFilter::new().user_id(vec![&1]).name(vec![&name]);
+        let mut query = QueryBuilder::<Postgres>::new("SELECT * FROM SchematicNode SN WHERE true");
+        filter.push_conditions(&SchemaMapping::default(), &mut query);
+
+        assert_eq!(query.into_sql(), "SELECT * FROM SchematicNode SN WHERE true AND (SN.NodeOwner = $1) AND (SN.NodeName LIKE $2 ESCAPE '!')");
+    }
+
+    #[test]
+    fn test_filter_node_columns() {
+        let warship = "WARSHIP".to_string();
+        let filter = SchematicFilter::new()
+            .node_type(vec![&warship])
+            .modified_after(parse_date("2024-03-01").unwrap())
+            .modified_since(parse_date("2024-03-02").unwrap())
+            .parent(vec![&4])
+            .id(vec![&1, &2]);
+
+        assert_eq!(build(&filter), "SELECT * FROM SchematicNode SN WHERE true AND SN.NodeType IN (?) AND SN.LastUpdate > ? AND SN.LastUpdate >= ? \
+            AND SN.NodeId IN (WITH RECURSIVE folder (id) AS (SELECT NodeId FROM SchematicNode WHERE NodeId IN (?) \
+            UNION SELECT C.NodeId FROM SchematicNode C INNER JOIN folder F ON C.ParentNode = F.id) SELECT id FROM folder) \
+            AND SN.NodeId IN (?, ?)");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2024-03-01").unwrap().to_string(), "2024-03-01 00:00:00");
+        assert_eq!(parse_date("2024-03-01 12:30:00").unwrap().to_string(), "2024-03-01 12:30:00");
+        assert_eq!(parse_date("2024-03-01T12:30:00").unwrap().to_string(), "2024-03-01 12:30:00");
+        assert!(parse_date("01.03.2024").is_err());
+    }
+
+    #[test]
+    fn test_like_pattern() {
+        assert_eq!(like_pattern("x' OR '1'='1"), "%x' OR '1'='1%");
+        assert_eq!(like_pattern("100%_done!"), "%100!%!_done!!%");
+    }
+}
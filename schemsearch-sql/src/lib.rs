@@ -15,57 +15,109 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::sync::Mutex;
-use sqlx::{Executor, MySql, Pool, Row};
-use sqlx::mysql::{MySqlConnectOptions, MySqlPoolOptions};
+use chrono::NaiveDateTime;
+use crate::config::ConnectionConfig;
 use crate::filter::SchematicFilter;
+use crate::loader::{LoaderOptions, SchemdataLoader};
+use crate::results::MatchRecord;
+use crate::storage::{AnyStorage, SchematicStorage};
 
-mod properties;
+pub mod config;
 pub mod filter;
-
-static mut CONN: Mutex<Option<Pool<MySql>>> = Mutex::new(None);
+pub mod loader;
+pub mod mapping;
+pub mod results;
+pub mod storage;
 
 pub struct SchematicNode {
     pub id: i32,
     pub name: String
 }
 
-pub async unsafe fn get_connection() {
-    let mut conn = CONN.lock().unwrap();
-    if conn.is_none() {
-        let properties = properties::load_mysql_properties();
-        let _ = conn.insert(MySqlPoolOptions::new()
-            .max_connections(5)
-            .connect_with(MySqlConnectOptions::new()
-                .host(properties.host.as_str())
-                .port(3306)
-                .username(properties.user.as_str())
-                .password(properties.password.as_str())
-                .database(properties.database.as_str()))
-            .await.expect("Failed to connect to database"));
-    }
+/// A connection pool to a schematic database, independent of any other `SchematicDatabase`
+pub struct SchematicDatabase {
+    storage: AnyStorage,
 }
 
-pub async fn load_all_schematics(filter: SchematicFilter) -> Vec<SchematicNode> {
-    unsafe { get_connection().await; }
-    let mut schematics = Vec::new();
-    let rows = unsafe { &CONN }.lock().unwrap().as_mut().unwrap().fetch_all(format!("SELECT SN.NodeId, SN.NodeName FROM NodeData ND INNER JOIN SchematicNode SN ON SN.NodeId = ND.NodeId WHERE NodeFormat = true {}", filter.build()).as_str()).await.expect("Failed to fetch schematics");
-    for row in rows {
-        schematics.push(SchematicNode {
-            id: row.get(0),
-            name: row.get(1)
-        });
+impl SchematicDatabase {
+    pub fn new(storage: AnyStorage) -> SchematicDatabase {
+        SchematicDatabase { storage }
     }
-    schematics
-}
 
-pub async fn load_schemdata(id: i32) -> Vec<u8> {
-    unsafe { get_connection().await; }
-    let rows = unsafe { &CONN }.lock().unwrap().as_mut().unwrap().fetch_one(format!("SELECT SchemData FROM NodeData WHERE NodeId = {}", id).as_str()).await.expect("Failed to fetch schematics");
-    rows.get(0)
-}
+    pub async fn connect(config: &ConnectionConfig) -> Result<SchematicDatabase, String> {
+        Ok(SchematicDatabase::new(config.connect().await?))
+    }
+
+    pub fn storage(&self) -> &AnyStorage {
+        &self.storage
+    }
 
-pub async fn close() {
-    unsafe { CONN.lock().unwrap().take().unwrap().close().await }
+    pub async fn load_all_schematics(&self, filter: &SchematicFilter) -> Result<Vec<SchematicNode>, String> {
+        self.storage.load_all_schematics(filter).await
+    }
+
+    pub async fn load_schemdata(&self, id: i32) -> Result<Vec<u8>, String> {
+        self.storage.load_schemdata(id).await
+    }
+
+    /// Streams the data of the nodes `ids`, see [SchemdataLoader]
+    pub fn stream_schemdata(&self, ids: Vec<i32>, options: LoaderOptions) -> SchemdataLoader {
+        SchemdataLoader::start(self.storage.clone(), ids, options)
+    }
+
+    pub async fn create_results_table(&self, table: &str) -> Result<(), String> {
+        self.storage.create_results_table(table).await
+    }
+
+    pub async fn insert_matches(&self, table: &str, matches: &[MatchRecord]) -> Result<(), String> {
+        self.storage.insert_matches(table, matches).await
+    }
+
+    pub async fn current_time(&self) -> Result<NaiveDateTime, String> {
+        self.storage.current_time().await
+    }
+
+    /// Closes the pool, running queries are finished first
+    pub async fn close(&self) {
+        self.storage.close().await
+    }
 }
 
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use futures::executor::block_on;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use crate::storage::SqliteStorage;
+    use super::*;
+
+    async fn database(names: &[&str]) -> SchematicDatabase {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let storage = SqliteStorage::create_with(options, SqlitePoolOptions::new().max_connections(1)).await.unwrap();
+        for (id, name) in names.iter().enumerate() {
+            sqlx::query("INSERT INTO SchematicNode (NodeId, NodeName, NodeOwner) VALUES (?, ?, 1)")
+                .bind(id as i32).bind(name).execute(storage.pool()).await.unwrap();
+            sqlx::query("INSERT INTO NodeData (NodeId, NodeFormat, SchemData) VALUES (?, true, ?)")
+                .bind(id as i32).bind(name.as_bytes()).execute(storage.pool()).await.unwrap();
+        }
+        SchematicDatabase::new(AnyStorage::Sqlite(storage))
+    }
+
+    #[test]
+    fn test_separate_databases() {
+        block_on(async {
+            let first = database(&["first"]).await;
+            let second = database(&["second", "third"]).await;
+
+            assert_eq!(first.load_all_schematics(&SchematicFilter::default()).await.unwrap().len(), 1);
+            assert_eq!(second.load_all_schematics(&SchematicFilter::default()).await.unwrap().len(), 2);
+            assert_eq!(first.load_schemdata(0).await.unwrap(), b"first");
+            assert_eq!(second.load_schemdata(0).await.unwrap(), b"second");
+
+            first.close().await;
+            assert!(first.load_schemdata(0).await.is_err());
+            assert_eq!(second.load_schemdata(1).await.unwrap(), b"third");
+        });
+    }
+}
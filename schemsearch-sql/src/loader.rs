@@ -0,0 +1,145 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::panic::resume_unwind;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::{spawn, JoinHandle};
+use futures::executor::block_on;
+use futures::stream::{iter, StreamExt};
+use crate::storage::SchematicStorage;
+
+pub const DEFAULT_BATCH_SIZE: usize = 16;
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoaderOptions {
+    /// Nodes fetched by one query
+    pub batch_size: usize,
+    /// Queries running at the same time, each one uses a connection of the pool
+    pub concurrency: usize,
+    /// Fetched nodes waiting for the consumer, `0` uses `batch_size * concurrency`
+    pub queue_size: usize,
+}
+
+impl Default for LoaderOptions {
+    fn default() -> Self {
+        LoaderOptions {
+            batch_size: DEFAULT_BATCH_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            queue_size: 0,
+        }
+    }
+}
+
+/// Fetches the data of nodes in batches on a background thread and yields it in the order the batches complete.
+/// Every id is yielded once, with an error if its batch failed or it has no data.
+/// The loader waits while the queue is full, so at most `queue_size` nodes and the running batches are held in memory.
+pub struct SchemdataLoader {
+    receiver: Receiver<(i32, Result<Vec<u8>, String>)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SchemdataLoader {
+    pub fn start<S: SchematicStorage + Send + 'static>(storage: S, ids: Vec<i32>, options: LoaderOptions) -> SchemdataLoader {
+        let batch_size = options.batch_size.max(1);
+        let concurrency = options.concurrency.max(1);
+        let queue_size = match options.queue_size {
+            0 => batch_size * concurrency,
+            size => size,
+        };
+        let (sender, receiver) = sync_channel(queue_size);
+        let handle = spawn(move || block_on(async {
+            let storage = &storage;
+            let mut batches = iter(ids.chunks(batch_size))
+                .map(|batch| async move { (batch, storage.load_schemdata_batch(batch).await) })
+                .buffer_unordered(concurrency);
+            while let Some((batch, result)) = batches.next().await {
+                for item in batch_results(batch, result) {
+                    if sender.send(item).is_err() {
+                        // the consumer is gone
+                        return;
+                    }
+                }
+            }
+        }));
+        SchemdataLoader { receiver, handle: Some(handle) }
+    }
+}
+
+impl Iterator for SchemdataLoader {
+    type Item = (i32, Result<Vec<u8>, String>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.receiver.recv() {
+            Ok(item) => Some(item),
+            Err(_) => {
+                if let Some(Err(e)) = self.handle.take().map(JoinHandle::join) {
+                    resume_unwind(e);
+                }
+                None
+            }
+        }
+    }
+}
+
+fn batch_results(batch: &[i32], result: Result<Vec<(i32, Vec<u8>)>, String>) -> Vec<(i32, Result<Vec<u8>, String>)> {
+    match result {
+        Ok(mut rows) => batch.iter().map(|id| match rows.iter().position(|(row, _)| row == id) {
+            Some(i) => (*id, Ok(rows.swap_remove(i).1)),
+            None => (*id, Err(format!("No schematic data for node {}", id))),
+        }).collect(),
+        Err(e) => batch.iter().map(|id| (*id, Err(e.clone()))).collect(),
+    }
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use crate::storage::SqliteStorage;
+    use super::*;
+
+    #[test]
+    fn test_loader() {
+        let storage = block_on(async {
+            let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+            let storage = SqliteStorage::create_with(options, SqlitePoolOptions::new().max_connections(1)).await.unwrap();
+            for id in 1..=5 {
+                sqlx::query("INSERT INTO SchematicNode (NodeId, NodeName, NodeOwner) VALUES (?, 'node', 1)")
+                    .bind(id).execute(storage.pool()).await.unwrap();
+                sqlx::query("INSERT INTO NodeData (NodeId, NodeFormat, SchemData) VALUES (?, true, ?)")
+                    .bind(id).bind(vec![id as u8; 3]).execute(storage.pool()).await.unwrap();
+            }
+            storage
+        });
+        let options = LoaderOptions { batch_size: 2, concurrency: 3, queue_size: 1 };
+        let mut loaded: Vec<(i32, Result<Vec<u8>, String>)> = SchemdataLoader::start(storage, vec![1, 2, 3, 42, 4, 5], options).collect();
+        loaded.sort_by_key(|(id, _)| *id);
+
+        assert_eq!(loaded.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 42]);
+        assert_eq!(loaded[2].1, Ok(vec![3, 3, 3]));
+        assert!(loaded[5].1.is_err());
+    }
+
+    #[test]
+    fn test_batch_results() {
+        let results = batch_results(&[1, 2], Err("offline".to_string()));
+
+        assert_eq!(results, vec![(1, Err("offline".to_string())), (2, Err("offline".to_string()))]);
+    }
+}
@@ -0,0 +1,125 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+/// Table and column names of the schematic database, the defaults match SteamWar's tables.
+/// Queries use `SN` as the alias of the node table and `ND` for the data table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaMapping {
+    pub node_table: String,
+    pub data_table: String,
+    /// Id of the node table
+    pub id_column: String,
+    pub name_column: String,
+    pub owner_column: String,
+    pub type_column: String,
+    /// Parent folder of a node, `NULL` at the top level
+    pub parent_column: String,
+    /// Time of the last change
+    pub modified_column: String,
+    /// Node id in the data table
+    pub data_id_column: String,
+    pub data_column: String,
+    /// SQL condition for rows that contain Sponge schematics, e.g. `ND.Format = 'sponge'`
+    pub sponge_format: String,
+}
+
+impl Default for SchemaMapping {
+    fn default() -> Self {
+        SchemaMapping {
+            node_table: "SchematicNode".to_string(),
+            data_table: "NodeData".to_string(),
+            id_column: "NodeId".to_string(),
+            name_column: "NodeName".to_string(),
+            owner_column: "NodeOwner".to_string(),
+            type_column: "NodeType".to_string(),
+            parent_column: "ParentNode".to_string(),
+            modified_column: "LastUpdate".to_string(),
+            data_id_column: "NodeId".to_string(),
+            data_column: "SchemData".to_string(),
+            sponge_format: "ND.NodeFormat = true".to_string(),
+        }
+    }
+}
+
+impl SchemaMapping {
+    pub const KEYS: [&'static str; 11] = [
+        "node_table", "data_table", "id_column", "name_column", "owner_column", "type_column", "parent_column", "modified_column",
+        "data_id_column", "data_column", "sponge_format",
+    ];
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let field = match key {
+            "node_table" => &mut self.node_table,
+            "data_table" => &mut self.data_table,
+            "id_column" => &mut self.id_column,
+            "name_column" => &mut self.name_column,
+            "owner_column" => &mut self.owner_column,
+            "type_column" => &mut self.type_column,
+            "parent_column" => &mut self.parent_column,
+            "modified_column" => &mut self.modified_column,
+            "data_id_column" => &mut self.data_id_column,
+            "data_column" => &mut self.data_column,
+            "sponge_format" => &mut self.sponge_format,
+            _ => return Err(format!("Unknown setting '{}'", key)),
+        };
+        *field = value.to_string();
+        self.validate()
+    }
+
+    /// Table and column names are put into the queries as they are, so only plain (optionally schema qualified) names are allowed.
+    /// `sponge_format` is trusted SQL.
+    pub fn validate(&self) -> Result<(), String> {
+        for name in [&self.node_table, &self.data_table, &self.id_column, &self.name_column, &self.owner_column,
+            &self.type_column, &self.parent_column, &self.modified_column, &self.data_id_column, &self.data_column] {
+            if !is_identifier(name) {
+                return Err(format!("Invalid table or column name '{}'", name));
+            }
+        }
+        if self.sponge_format.trim().is_empty() {
+            return Err("The Sponge format condition is empty, use 'true' to accept all rows".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn is_identifier(name: &str) -> bool {
+    name.split('.').all(|part| {
+        let mut chars = part.chars();
+        chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let mut mapping = SchemaMapping::default();
+        mapping.set("node_table", "public.schematics").unwrap();
+        mapping.set("sponge_format", "ND.format = 'sponge'").unwrap();
+
+        assert_eq!(mapping.node_table, "public.schematics");
+        assert_eq!(mapping.sponge_format, "ND.format = 'sponge'");
+        assert_eq!(mapping.set("name_column", "name; DROP TABLE x").unwrap_err(), "Invalid table or column name 'name; DROP TABLE x'");
+        assert!(mapping.set("data_table", "1data").is_err());
+        assert!(mapping.set("data_table", "a..b").is_err());
+        assert!(mapping.set("colour", "red").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use chrono::NaiveDateTime;
+use schemsearch_lib::Match;
+
+/// Rows are inserted in batches to stay below the bind parameter limits
+pub(crate) const INSERT_BATCH_SIZE: usize = 100;
+
+/// A match found in a schematic node, stored by `SchematicStorage::insert_matches`
+#[derive(Debug, Clone)]
+pub struct MatchRecord {
+    pub node_id: i32,
+    /// Identifies the pattern, e.g. its path
+    pub pattern: String,
+    pub found: Match,
+    pub run_at: NaiveDateTime,
+}
+
+pub(crate) fn create_table_sql(table: &str) -> String {
+    format!("CREATE TABLE IF NOT EXISTS {} (
+        NodeId INTEGER NOT NULL,
+        Pattern TEXT NOT NULL,
+        X INTEGER NOT NULL,
+        Y INTEGER NOT NULL,
+        Z INTEGER NOT NULL,
+        WorldX INTEGER NOT NULL,
+        WorldY INTEGER NOT NULL,
+        WorldZ INTEGER NOT NULL,
+        Percent DOUBLE PRECISION NOT NULL,
+        RunAt TIMESTAMP NOT NULL
+    )", table)
+}
+
+pub(crate) fn insert_sql(table: &str) -> String {
+    format!("INSERT INTO {} (NodeId, Pattern, X, Y, Z, WorldX, WorldY, WorldZ, Percent, RunAt) ", table)
+}
@@ -0,0 +1,504 @@
+/*
+ * Copyright (C) 2023  Chaoscaot
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::future::Future;
+use std::str::FromStr;
+use chrono::NaiveDateTime;
+use sqlx::{Database, MySql, Pool, Postgres, QueryBuilder, Row, Sqlite};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use crate::config::ConnectionConfig;
+use crate::filter::{push_list, SchematicFilter};
+use crate::mapping::{is_identifier, SchemaMapping};
+use crate::results::{create_table_sql, insert_sql, MatchRecord, INSERT_BATCH_SIZE};
+use crate::SchematicNode;
+
+/// Access to the schematic nodes and their data in a database, see [SchemaMapping]
+pub trait SchematicStorage {
+    fn load_all_schematics(&self, filter: &SchematicFilter) -> impl Future<Output = Result<Vec<SchematicNode>, String>>;
+
+    fn load_schemdata(&self, id: i32) -> impl Future<Output = Result<Vec<u8>, String>>;
+
+    /// Loads the data of several nodes with one query, nodes without data are left out
+    fn load_schemdata_batch(&self, ids: &[i32]) -> impl Future<Output = Result<Vec<(i32, Vec<u8>)>, String>>;
+
+    /// Creates the results `table` if it doesn't exist
+    fn create_results_table(&self, table: &str) -> impl Future<Output = Result<(), String>>;
+
+    fn insert_matches(&self, table: &str, matches: &[MatchRecord]) -> impl Future<Output = Result<(), String>>;
+
+    /// The clock of the database, comparable to the modification times of the nodes
+    fn current_time(&self) -> impl Future<Output = Result<NaiveDateTime, String>>;
+
+    fn close(&self) -> impl Future<Output = ()>;
+}
+
+/// Tables used by the SteamWar schematic system, for databases created by schemsearch
+const SQLITE_SCHEMA: [&str; 2] = [
+    "CREATE TABLE IF NOT EXISTS SchematicNode (
+        NodeId INTEGER PRIMARY KEY AUTOINCREMENT,
+        NodeName TEXT NOT NULL,
+        NodeOwner INTEGER NOT NULL,
+        ParentNode INTEGER NULL REFERENCES SchematicNode (NodeId) ON DELETE CASCADE,
+        LastUpdate TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        NodeItem TEXT NOT NULL DEFAULT '',
+        NodeType TEXT NULL,
+        NodeRank INTEGER NOT NULL DEFAULT 0,
+        ReplaceColor BOOLEAN NOT NULL DEFAULT true,
+        AllowReplay BOOLEAN NOT NULL DEFAULT true,
+        Config INTEGER NOT NULL DEFAULT 0
+    )",
+    "CREATE TABLE IF NOT EXISTS NodeData (
+        NodeId INTEGER NOT NULL PRIMARY KEY REFERENCES SchematicNode (NodeId) ON DELETE CASCADE,
+        CreatedAt TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        NodeFormat BOOLEAN NOT NULL,
+        SchemData BLOB NOT NULL
+    )",
+];
+
+pub struct SqlStorage<DB: Database> {
+    pool: Pool<DB>,
+    mapping: SchemaMapping,
+}
+
+pub type MySqlStorage = SqlStorage<MySql>;
+pub type PgStorage = SqlStorage<Postgres>;
+pub type SqliteStorage = SqlStorage<Sqlite>;
+
+/// A storage for any of the supported backends, selected by the scheme of the connection URL
+#[derive(Clone)]
+pub enum AnyStorage {
+    MySql(MySqlStorage),
+    Postgres(PgStorage),
+    Sqlite(SqliteStorage),
+}
+
+impl<DB: Database> SqlStorage<DB> {
+    pub fn new(pool: Pool<DB>) -> SqlStorage<DB> {
+        SqlStorage { pool, mapping: SchemaMapping::default() }
+    }
+
+    pub fn with_mapping(mut self, mapping: SchemaMapping) -> SqlStorage<DB> {
+        self.mapping = mapping;
+        self
+    }
+
+    pub fn pool(&self) -> &Pool<DB> {
+        &self.pool
+    }
+
+    pub fn mapping(&self) -> &SchemaMapping {
+        &self.mapping
+    }
+}
+
+impl<DB: Database> Clone for SqlStorage<DB> {
+    fn clone(&self) -> Self {
+        SqlStorage { pool: self.pool.clone(), mapping: self.mapping.clone() }
+    }
+}
+
+impl SqliteStorage {
+    /// Opens an existing SQLite database like `sqlite://schematics.db` without changing its tables
+    pub async fn open(url: &str) -> Result<SqliteStorage, String> {
+        let options = SqliteConnectOptions::from_str(url).map_err(|e| e.to_string())?;
+        SqliteStorage::open_with(options, SqlitePoolOptions::new()).await
+    }
+
+    pub async fn open_with(options: SqliteConnectOptions, pool: SqlitePoolOptions) -> Result<SqliteStorage, String> {
+        let pool = pool
+            .connect_with(options)
+            .await.map_err(|e| format!("Failed to open database: {}", e))?;
+        Ok(SqlStorage::new(pool))
+    }
+
+    /// Like `open`, but creates the file and the SteamWar tables if needed
+    pub async fn create(url: &str) -> Result<SqliteStorage, String> {
+        let options = SqliteConnectOptions::from_str(url).map_err(|e| e.to_string())?;
+        SqliteStorage::create_with(options, SqlitePoolOptions::new()).await
+    }
+
+    pub async fn create_with(options: SqliteConnectOptions, pool: SqlitePoolOptions) -> Result<SqliteStorage, String> {
+        let storage = SqliteStorage::open_with(options.create_if_missing(true), pool).await?;
+        storage.create_tables().await?;
+        Ok(storage)
+    }
+
+    pub async fn create_tables(&self) -> Result<(), String> {
+        for statement in SQLITE_SCHEMA {
+            sqlx::query(statement).execute(&self.pool).await.map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+// The queries are shared by all backends, but QueryBuilder arguments only line up for concrete databases
+macro_rules! impl_schematic_storage {
+    ($db:ty, $now:literal) => {
+        impl SchematicStorage for SqlStorage<$db> {
+            async fn load_all_schematics(&self, filter: &SchematicFilter) -> Result<Vec<SchematicNode>, String> {
+                let m = &self.mapping;
+                let mut query = QueryBuilder::<$db>::new(format!(
+                    "SELECT SN.{}, SN.{} FROM {} ND INNER JOIN {} SN ON SN.{} = ND.{} WHERE ({})",
+                    m.id_column, m.name_column, m.data_table, m.node_table, m.id_column, m.data_id_column, m.sponge_format,
+                ));
+                filter.push_conditions(m, &mut query);
+                let rows = query.build().fetch_all(&self.pool).await.map_err(|e| e.to_string())?;
+                rows.iter().map(|row| Ok(SchematicNode {
+                    id: row.try_get(0).map_err(|e| e.to_string())?,
+                    name: row.try_get(1).map_err(|e| e.to_string())?,
+                })).collect()
+            }
+
+            async fn load_schemdata(&self, id: i32) -> Result<Vec<u8>, String> {
+                let m = &self.mapping;
+                let mut query = QueryBuilder::<$db>::new(format!("SELECT {} FROM {} WHERE {} = ", m.data_column, m.data_table, m.data_id_column));
+                query.push_bind(id);
+                let row = query.build().fetch_one(&self.pool).await.map_err(|e| e.to_string())?;
+                row.try_get(0).map_err(|e| e.to_string())
+            }
+
+            async fn load_schemdata_batch(&self, ids: &[i32]) -> Result<Vec<(i32, Vec<u8>)>, String> {
+                if ids.is_empty() {
+                    return Ok(Vec::new());
+                }
+                let m = &self.mapping;
+                let mut query = QueryBuilder::<$db>::new(format!("SELECT {}, {} FROM {} WHERE {} IN (", m.data_id_column, m.data_column, m.data_table, m.data_id_column));
+                push_list(&mut query, ids.iter().copied());
+                query.push(")");
+                let rows = query.build().fetch_all(&self.pool).await.map_err(|e| e.to_string())?;
+                rows.iter().map(|row| Ok((
+                    row.try_get(0).map_err(|e| e.to_string())?,
+                    row.try_get(1).map_err(|e| e.to_string())?,
+                ))).collect()
+            }
+
+            async fn create_results_table(&self, table: &str) -> Result<(), String> {
+                results_table(table)?;
+                sqlx::query(&create_table_sql(table)).execute(&self.pool).await.map_err(|e| e.to_string())?;
+                Ok(())
+            }
+
+            async fn insert_matches(&self, table: &str, matches: &[MatchRecord]) -> Result<(), String> {
+                results_table(table)?;
+                let mut transaction = self.pool.begin().await.map_err(|e| e.to_string())?;
+                for batch in matches.chunks(INSERT_BATCH_SIZE) {
+                    let mut query = QueryBuilder::<$db>::new(insert_sql(table));
+                    query.push_values(batch, |mut row, record| {
+                        let found = &record.found;
+                        row.push_bind(record.node_id)
+                            .push_bind(record.pattern.clone())
+                            .push_bind(found.x as i32)
+                            .push_bind(found.y as i32)
+                            .push_bind(found.z as i32)
+                            .push_bind(found.world_x)
+                            .push_bind(found.world_y)
+                            .push_bind(found.world_z)
+                            .push_bind(found.percent as f64)
+                            .push_bind(record.run_at);
+                    });
+                    query.build().execute(&mut *transaction).await.map_err(|e| e.to_string())?;
+                }
+                transaction.commit().await.map_err(|e| e.to_string())
+            }
+
+            async fn current_time(&self) -> Result<NaiveDateTime, String> {
+                let row = sqlx::query($now).fetch_one(&self.pool).await.map_err(|e| e.to_string())?;
+                row.try_get(0).map_err(|e| e.to_string())
+            }
+
+            async fn close(&self) {
+                self.pool.close().await
+            }
+        }
+    };
+}
+
+fn results_table(table: &str) -> Result<(), String> {
+    if is_identifier(table) {
+        Ok(())
+    } else {
+        Err(format!("Invalid table name '{}'", table))
+    }
+}
+
+// CURRENT_TIMESTAMP has a time zone in Postgres, LOCALTIMESTAMP matches columns without one
+impl_schematic_storage!(MySql, "SELECT LOCALTIMESTAMP");
+impl_schematic_storage!(Postgres, "SELECT LOCALTIMESTAMP");
+impl_schematic_storage!(Sqlite, "SELECT CURRENT_TIMESTAMP");
+
+impl AnyStorage {
+    /// Connects to `mysql://`, `postgres://` (or `postgresql://`) and `sqlite:` URLs
+    pub async fn connect(url: &str, max_connections: u32) -> Result<AnyStorage, String> {
+        ConnectionConfig {
+            pool_size: Some(max_connections),
+            ..ConnectionConfig::from_url(url)
+        }.connect().await
+    }
+}
+
+impl SchematicStorage for AnyStorage {
+    async fn load_all_schematics(&self, filter: &SchematicFilter) -> Result<Vec<SchematicNode>, String> {
+        match self {
+            AnyStorage::MySql(storage) => storage.load_all_schematics(filter).await,
+            AnyStorage::Postgres(storage) => storage.load_all_schematics(filter).await,
+            AnyStorage::Sqlite(storage) => storage.load_all_schematics(filter).await,
+        }
+    }
+
+    async fn load_schemdata(&self, id: i32) -> Result<Vec<u8>, String> {
+        match self {
+            AnyStorage::MySql(storage) => storage.load_schemdata(id).await,
+            AnyStorage::Postgres(storage) => storage.load_schemdata(id).await,
+            AnyStorage::Sqlite(storage) => storage.load_schemdata(id).await,
+        }
+    }
+
+    async fn load_schemdata_batch(&self, ids: &[i32]) -> Result<Vec<(i32, Vec<u8>)>, String> {
+        match self {
+            AnyStorage::MySql(storage) => storage.load_schemdata_batch(ids).await,
+            AnyStorage::Postgres(storage) => storage.load_schemdata_batch(ids).await,
+            AnyStorage::Sqlite(storage) => storage.load_schemdata_batch(ids).await,
+        }
+    }
+
+    async fn create_results_table(&self, table: &str) -> Result<(), String> {
+        match self {
+            AnyStorage::MySql(storage) => storage.create_results_table(table).await,
+            AnyStorage::Postgres(storage) => storage.create_results_table(table).await,
+            AnyStorage::Sqlite(storage) => storage.create_results_table(table).await,
+        }
+    }
+
+    async fn insert_matches(&self, table: &str, matches: &[MatchRecord]) -> Result<(), String> {
+        match self {
+            AnyStorage::MySql(storage) => storage.insert_matches(table, matches).await,
+            AnyStorage::Postgres(storage) => storage.insert_matches(table, matches).await,
+            AnyStorage::Sqlite(storage) => storage.insert_matches(table, matches).await,
+        }
+    }
+
+    async fn current_time(&self) -> Result<NaiveDateTime, String> {
+        match self {
+            AnyStorage::MySql(storage) => storage.current_time().await,
+            AnyStorage::Postgres(storage) => storage.current_time().await,
+            AnyStorage::Sqlite(storage) => storage.current_time().await,
+        }
+    }
+
+    async fn close(&self) {
+        match self {
+            AnyStorage::MySql(storage) => storage.close().await,
+            AnyStorage::Postgres(storage) => storage.close().await,
+            AnyStorage::Sqlite(storage) => storage.close().await,
+        }
+    }
+}
+
+#[allow(unused_imports)]
+#[cfg(test)]
+mod tests {
+    use std::fs::read;
+    use futures::executor::block_on;
+    use schemsearch_files::SpongeSchematic;
+    use schemsearch_lib::Match;
+    use crate::filter::parse_date;
+    use super::*;
+
+    async fn storage() -> SqliteStorage {
+        // every connection to sqlite::memory: opens its own database
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+        let storage = SqliteStorage::create_with(options, SqlitePoolOptions::new().max_connections(1)).await.unwrap();
+        let data = read("../tests/simple.schem").unwrap();
+        sqlx::query("INSERT INTO SchematicNode (NodeId, NodeName, NodeOwner, ParentNode) VALUES (10, 'folder', 1, NULL), (11, 'nested', 1, 10), (12, 'cycle', 1, 13), (13, 'cycle', 1, 12)")
+            .execute(storage.pool()).await.unwrap();
+        for (id, name, owner, format, node_type, parent, modified) in [
+            (1, "simple", 1, true, "WARSHIP", Some(10), "2024-01-01 00:00:00"),
+            (2, "x' OR '1'='1", 2, true, "WARGEAR", None, "2024-06-01 00:00:00"),
+            (3, "100%_done", 1, true, "WARGEAR", Some(11), "2024-06-01 12:00:00"),
+            (4, "legacy", 1, false, "WARGEAR", None, "2024-06-01 00:00:00"),
+        ] {
+            sqlx::query("INSERT INTO SchematicNode (NodeId, NodeName, NodeOwner, NodeType, ParentNode, LastUpdate) VALUES (?, ?, ?, ?, ?, ?)")
+                .bind(id).bind(name).bind(owner).bind(node_type).bind(parent).bind(modified)
+                .execute(storage.pool()).await.unwrap();
+            sqlx::query("INSERT INTO NodeData (NodeId, NodeFormat, SchemData) VALUES (?, ?, ?)")
+                .bind(id).bind(format).bind(&data)
+                .execute(storage.pool()).await.unwrap();
+        }
+        storage
+    }
+
+    fn names(nodes: Vec<SchematicNode>) -> Vec<String> {
+        let mut names: Vec<String> = nodes.into_iter().map(|node| node.name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_sqlite_load_all() {
+        block_on(async {
+            let storage = storage().await;
+            let nodes = storage.load_all_schematics(&SchematicFilter::default()).await.unwrap();
+
+            assert_eq!(names(nodes), vec!["100%_done", "simple", "x' OR '1'='1"]);
+        });
+    }
+
+    #[test]
+    fn test_sqlite_filter() {
+        block_on(async {
+            let storage = storage().await;
+            let hostile = "x' OR '1'='1".to_string();
+            let percent = "%".to_string();
+
+            let nodes = storage.load_all_schematics(&SchematicFilter::new().name(vec![&hostile])).await.unwrap();
+            assert_eq!(names(nodes), vec![hostile.clone()]);
+            let nodes = storage.load_all_schematics(&SchematicFilter::new().name(vec![&percent])).await.unwrap();
+            assert_eq!(names(nodes), vec!["100%_done"]);
+            let nodes = storage.load_all_schematics(&SchematicFilter::new().user_id(vec![&1])).await.unwrap();
+            assert_eq!(names(nodes), vec!["100%_done", "simple"]);
+        });
+    }
+
+    #[test]
+    fn test_sqlite_node_filter() {
+        block_on(async {
+            let storage = storage().await;
+            let warship = "WARSHIP".to_string();
+            let load = |filter: SchematicFilter| {
+                let storage = storage.clone();
+                async move { names(storage.load_all_schematics(&filter).await.unwrap()) }
+            };
+
+            assert_eq!(load(SchematicFilter::new().node_type(vec![&warship])).await, vec!["simple"]);
+            assert_eq!(load(SchematicFilter::new().modified_after(parse_date("2024-06-01 06:00:00").unwrap())).await, vec!["100%_done"]);
+            assert_eq!(load(SchematicFilter::new().modified_after(parse_date("2024-06-01 12:00:00").unwrap())).await, Vec::<String>::new());
+            assert_eq!(load(SchematicFilter::new().modified_since(parse_date("2024-06-01 12:00:00").unwrap())).await, vec!["100%_done"]);
+            assert_eq!(load(SchematicFilter::new().parent(vec![&10])).await, vec!["100%_done", "simple"]);
+            assert_eq!(load(SchematicFilter::new().parent(vec![&11])).await, vec!["100%_done"]);
+            assert_eq!(load(SchematicFilter::new().parent(vec![&10, &11])).await, vec!["100%_done", "simple"]);
+            assert_eq!(load(SchematicFilter::new().parent(vec![&12])).await, Vec::<String>::new());
+            assert_eq!(load(SchematicFilter::new().id(vec![&2, &4])).await, vec!["x' OR '1'='1"]);
+        });
+    }
+
+    #[test]
+    fn test_sqlite_results() {
+        block_on(async {
+            let storage = storage().await;
+            let run_at = parse_date("2024-06-01 12:00:00").unwrap();
+            let records: Vec<MatchRecord> = (0..250).map(|i| MatchRecord {
+                node_id: 1,
+                pattern: "cannon.schem".to_string(),
+                found: Match { x: i, y: 2, z: 3, world_x: -10, percent: 0.95, ..Match::default() },
+                run_at,
+            }).collect();
+
+            assert!(storage.create_results_table("results; DROP TABLE NodeData").await.is_err());
+            storage.create_results_table("results").await.unwrap();
+            storage.create_results_table("results").await.unwrap();
+            storage.insert_matches("results", &records).await.unwrap();
+
+            let row = sqlx::query("SELECT COUNT(*), MAX(X), MIN(WorldX), MIN(Percent), MIN(Pattern), MIN(RunAt) FROM results").fetch_one(storage.pool()).await.unwrap();
+            assert_eq!(row.get::<i32, _>(0), 250);
+            assert_eq!(row.get::<i32, _>(1), 249);
+            assert_eq!(row.get::<i32, _>(2), -10);
+            assert!((row.get::<f64, _>(3) - 0.95).abs() < 1e-6);
+            assert_eq!(row.get::<String, _>(4), "cannon.schem");
+            assert_eq!(row.get::<NaiveDateTime, _>(5), run_at);
+        });
+    }
+
+    #[test]
+    fn test_sqlite_current_time() {
+        block_on(async {
+            let storage = storage().await;
+            let now = storage.current_time().await.unwrap();
+            assert!(now > parse_date("2024-06-01 12:00:00").unwrap());
+        });
+    }
+
+    #[test]
+    fn test_connect_url() {
+        block_on(async {
+            let storage = AnyStorage::connect("sqlite::memory:", 1).await.unwrap();
+            assert!(matches!(storage, AnyStorage::Sqlite(_)));
+
+            let path = std::env::temp_dir().join(format!("schemsearch-connect-{}.db", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let url = format!("sqlite://{}", path.display());
+            assert!(AnyStorage::connect(&url, 1).await.is_err());
+            assert!(!path.exists());
+            SqliteStorage::create(&url).await.unwrap().pool().close().await;
+            let storage = AnyStorage::connect(&url, 1).await.unwrap();
+            assert!(storage.load_all_schematics(&SchematicFilter::default()).await.unwrap().is_empty());
+            storage.close().await;
+            std::fs::remove_file(&path).unwrap();
+
+            assert!(AnyStorage::connect("oracle://localhost/schematics", 1).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_sqlite_mapping() {
+        block_on(async {
+            let storage = AnyStorage::connect("sqlite::memory:", 1).await.unwrap();
+            let AnyStorage::Sqlite(storage) = storage else { unreachable!() };
+            sqlx::query("CREATE TABLE schematics (id INTEGER PRIMARY KEY, title TEXT, author INTEGER)").execute(storage.pool()).await.unwrap();
+            sqlx::query("CREATE TABLE blobs (schematic INTEGER, format TEXT, content BLOB)").execute(storage.pool()).await.unwrap();
+            sqlx::query("INSERT INTO schematics VALUES (1, 'Castle', 5), (2, 'Legacy', 5)").execute(storage.pool()).await.unwrap();
+            sqlx::query("INSERT INTO blobs VALUES (1, 'sponge', x'0102'), (2, 'mcedit', x'03')").execute(storage.pool()).await.unwrap();
+
+            let mut mapping = SchemaMapping::default();
+            for (key, value) in [
+                ("node_table", "schematics"), ("data_table", "blobs"), ("id_column", "id"), ("name_column", "title"),
+                ("owner_column", "author"), ("data_id_column", "schematic"), ("data_column", "content"), ("sponge_format", "ND.format = 'sponge'"),
+            ] {
+                mapping.set(key, value).unwrap();
+            }
+            let storage = storage.with_mapping(mapping);
+
+            let nodes = storage.load_all_schematics(&SchematicFilter::new().user_id(vec![&5])).await.unwrap();
+            assert_eq!(names(nodes), vec!["Castle"]);
+            assert_eq!(storage.load_schemdata(1).await.unwrap(), vec![1, 2]);
+        });
+    }
+
+    #[test]
+    fn test_sqlite_load_schemdata() {
+        block_on(async {
+            let storage = storage().await;
+            let data = storage.load_schemdata(1).await.unwrap();
+            let schematic = SpongeSchematic::load_data(&mut data.as_slice()).unwrap();
+
+            assert_eq!(schematic.block_data.len(), schematic.width as usize * schematic.height as usize * schematic.length as usize);
+            assert!(storage.load_schemdata(42).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_sqlite_load_schemdata_batch() {
+        block_on(async {
+            let storage = storage().await;
+            let mut rows = storage.load_schemdata_batch(&[4, 1, 42]).await.unwrap();
+            rows.sort_by_key(|(id, _)| *id);
+
+            assert_eq!(rows.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![1, 4]);
+            assert_eq!(rows[0].1, storage.load_schemdata(1).await.unwrap());
+            assert!(storage.load_schemdata_batch(&[]).await.unwrap().is_empty());
+        });
+    }
+}